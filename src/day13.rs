@@ -1,5 +1,7 @@
 use array2d::Array2D;
 use std::cmp::max;
+use std::io::{self, Write};
+use crate::netpbm;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Axis { X, Y }
@@ -59,6 +61,11 @@ pub fn print_grid(grid : &Grid) {
     println!();
 }
 
+/* Writes the grid as a PBM file, dots in black. */
+pub fn write_pbm<W: Write + ?Sized>(grid: &Grid, out: &mut W) -> io::Result<()> {
+    netpbm::write_pbm(out, grid.num_columns(), grid.num_rows(), |i, j| grid[(i, j)])
+}
+
 fn draw_grid(points: &[Point]) -> Grid {
     let width = points.iter().max_by_key(|&(x,_)| x).unwrap().0 as usize + 1;
     let height = points.iter().max_by_key(|&(_,y)| y).unwrap().1 as usize + 1;
@@ -132,6 +139,12 @@ fn count(grid: &Grid) -> u32 {
     count
 }
 
+/* The grid once all the folds are done, which reads as the code. */
+pub fn final_grid(input: &[u8]) -> Grid {
+    let (_,(points,folds)) = parser::parse(input).unwrap();
+    folds.iter().fold(draw_grid(&points), |grid, f| fold(&grid, f))
+}
+
 pub fn solve(input: &[u8]) -> (u32,u32) {
     let (_,(points,folds)) = parser::parse(input).unwrap();
     let mut grid = draw_grid(&points);
@@ -165,3 +178,16 @@ fn test13_1() {
     let solution = solve(include_bytes!("../inputs/day13.1"));
     assert_eq!(solution, (763,103));
 }
+
+#[test]
+fn test13_export() {
+    let mut out = Vec::new();
+    write_pbm(&final_grid(include_bytes!("../inputs/day13.0")), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "P1\n5 7\n11111\n10001\n10001\n10001\n11111\n00000\n00000\n");
+    let grid = final_grid(include_bytes!("../inputs/day13.1"));
+    assert_eq!(count(&grid), 103);
+    let mut out = Vec::new();
+    write_pbm(&grid, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2 + grid.num_rows());
+}
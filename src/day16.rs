@@ -6,119 +6,459 @@ fn bin_to_u32(bin: &[bool]) -> u64 {
     x
 }
 
-fn u32_to_bin(i: u8) -> [bool ; 4] {
-    let mut x = i;
-    let mut bin = [false ; 4];
-    for b in bin.iter_mut().rev() {
-        *b = x % 2 == 1;
-        x /= 2;
+/* Reads bits most significant first out of bytes, between a current
+   position and an end, both counted in bits from the start of the bytes. */
+#[derive(Debug,Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    end: usize
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8], len: usize) -> Self {
+        assert!(len <= 8 * bytes.len(), "{} bits in {} bytes", len, bytes.len());
+        BitReader { bytes, pos: 0, end: len }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    /* The next n bits as an integer, or None if fewer than n remain. */
+    pub fn read_bits(&mut self, n: usize) -> Option<u64> {
+        assert!(n <= 64, "Cannot read {} bits at once", n);
+        if n > self.remaining() {
+            return None;
+        }
+        let mut x = 0;
+        let mut n = n;
+        while n > 0 {
+            let offset = self.pos % 8;
+            let take = std::cmp::min(n, 8 - offset);
+            let byte = self.bytes[self.pos / 8] as u64;
+            x = x << take | (byte >> (8 - offset - take)) & ((1 << take) - 1);
+            self.pos += take;
+            n -= take;
+        }
+        Some(x)
+    }
+
+    /* A reader over the next len bits, which this reader skips. */
+    pub fn sub_reader(&mut self, len: usize) -> Option<BitReader<'a>> {
+        if len > self.remaining() {
+            return None;
+        }
+        let sub = BitReader { bytes: self.bytes, pos: self.pos, end: self.pos + len };
+        self.pos += len;
+        Some(sub)
     }
-    bin
 }
 
 mod parser {
-    use nom::{IResult, character::complete::*, multi::*, combinator::*};
+    use nom::{
+        IResult, character::complete::*, bytes::complete::*, multi::*,
+        combinator::*, sequence::*, branch::* };
     use super::*;
 
-    pub fn hexa(input: &[u8]) -> IResult<&[u8], [bool ; 4]> {
+    pub fn hexa(input: &[u8]) -> IResult<&[u8], u8> {
         let (input,c) = satisfy(|c|
-            ('0'..='9').contains(&c) ||
+            c.is_ascii_digit() ||
             ('A'..='F').contains(&c))(input)?;
-        let x = if ('0'..='9').contains(&c) {
+        let x = if c.is_ascii_digit() {
             c as u8 - b'0'
         }
         else {
             c as u8 - b'A' + 10
         };
-        Ok((input,u32_to_bin(x)))
+        Ok((input,x))
     }
 
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Vec<bool>> {
-        let (input, numbers) = many1(hexa)(input)?;
+    /* The transmission as bytes, with its length in bits. */
+    pub fn parse(input: &[u8]) -> IResult<&[u8], (Vec<u8>, usize)> {
+        let (input, digits) = many1(hexa)(input)?;
         let (input, _) = all_consuming(multispace0)(input)?;
-        Ok((input, numbers.concat()))
+        let bytes = digits.chunks(2)
+            .map(|pair| pair[0] << 4 | pair.get(1).unwrap_or(&0))
+            .collect();
+        Ok((input, (bytes, 4 * digits.len())))
+    }
+
+    /* Arithmetic expressions such as "min(1, 2*3) > 4", with the usual
+       precedences, compiled to packets of version 0. */
+    fn operator(type_id: u8, children: Vec<Packet>) -> Packet {
+        let length_type = if children.len() < 1 << 11 { LengthType::Count } else { LengthType::Bits };
+        Packet::Operator { version: 0, type_id, length_type, children }
+    }
+
+    fn symbol<'a>(s: &'static str) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+        delimited(space0, tag(s), space0)
+    }
+
+    fn atom(input: &[u8]) -> IResult<&[u8], Packet> {
+        alt((
+            map(u64, |value| Packet::Literal { version: 0, value }),
+            map(tuple((
+                alt((value(2, tag("min")), value(3, tag("max")))),
+                symbol("("),
+                separated_list1(symbol(","), comparison),
+                symbol(")"))),
+                |(type_id, _, children, _)| operator(type_id, children)),
+            delimited(symbol("("), comparison, symbol(")"))
+        ))(input)
+    }
+
+    fn chain(type_id: u8, first: Packet, mut rest: Vec<Packet>) -> Packet {
+        if rest.is_empty() {
+            first
+        }
+        else {
+            rest.insert(0, first);
+            operator(type_id, rest)
+        }
+    }
+
+    fn product(input: &[u8]) -> IResult<&[u8], Packet> {
+        let (input, first) = atom(input)?;
+        let (input, rest) = many0(preceded(symbol("*"), atom))(input)?;
+        Ok((input, chain(1, first, rest)))
+    }
+
+    fn sum(input: &[u8]) -> IResult<&[u8], Packet> {
+        let (input, first) = product(input)?;
+        let (input, rest) = many0(preceded(symbol("+"), product))(input)?;
+        Ok((input, chain(0, first, rest)))
+    }
+
+    fn comparison(input: &[u8]) -> IResult<&[u8], Packet> {
+        let (input, first) = sum(input)?;
+        let (input, rest) = opt(pair(
+            alt((value(5, symbol(">")), value(6, symbol("<")),
+                 value(7, symbol("==")), value(7, symbol("=")))),
+            sum))(input)?;
+        match rest {
+            Some((type_id, second)) => Ok((input, operator(type_id, vec![first, second]))),
+            None => Ok((input, first))
+        }
+    }
+
+    pub fn expression(input: &[u8]) -> IResult<&[u8], Packet> {
+        all_consuming(delimited(multispace0, comparison, multispace0))(input)
     }
 }
 
-fn parse_packet(packet: &[bool]) -> (u64, u64, usize) {
-    let version = bin_to_u32(&packet[0..3]);
-    let mut sum = version;
-    let typ = bin_to_u32(&packet[3..6]);
-    let mut s = 6;
+/* How an operator packet announces its subpackets: the total number of bits
+   they span (length type 0) or their count (length type 1). */
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum LengthType {
+    Bits,
+    Count
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Packet {
+    Literal { version: u8, value: u64 },
+    Operator { version: u8, type_id: u8, length_type: LengthType, children: Vec<Packet> }
+}
+
+fn operator_name(type_id: u8) -> &'static str {
+    match type_id {
+        0 => "+",
+        1 => "*",
+        2 => "min",
+        3 => "max",
+        5 => ">",
+        6 => "<",
+        7 => "=",
+        _ => panic!("Invalid operator type {}", type_id)
+    }
+}
+
+impl std::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Packet::Literal { value, .. } => write!(f, "{}", value),
+            Packet::Operator { type_id, children, .. } => {
+                write!(f, "({}", operator_name(*type_id))?;
+                for child in children {
+                    write!(f, " {}", child)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Packet {
+    pub fn version(&self) -> u8 {
+        match self {
+            Packet::Literal { version, .. } | Packet::Operator { version, .. } => *version
+        }
+    }
+
+    pub fn version_sum(&self) -> u64 {
+        match self {
+            Packet::Literal { version, .. } => *version as u64,
+            Packet::Operator { version, children, .. } =>
+                *version as u64 + children.iter().map(Packet::version_sum).sum::<u64>()
+        }
+    }
 
-    let value =
-        if typ == 4 { // Literal value
-            let mut bits = Vec::new();
-            loop {
-                let continued = packet[s];
-                bits.push(&packet[s+1..s+5]);
-                s += 5;
-                if !continued {
-                    break;
+    /* None if some intermediate value does not fit in a u64, or if the
+       packet was built by hand with the wrong number of operands. */
+    pub fn evaluate(&self) -> Option<u64> {
+        match self {
+            Packet::Literal { value, .. } => Some(*value),
+            Packet::Operator { type_id, children, .. } => {
+                let values = children.iter().map(Packet::evaluate).collect::<Option<Vec<u64>>>()?;
+                match type_id {
+                    0 => values.iter().try_fold(0u64, |acc, &v| acc.checked_add(v)),
+                    1 => values.iter().try_fold(1u64, |acc, &v| acc.checked_mul(v)),
+                    2 => values.iter().copied().min(),
+                    3 => values.iter().copied().max(),
+                    _ =>
+                        match values.as_slice() {
+                            [v1,v2] =>
+                                match type_id {
+                                    5 => Some(if v1 > v2 {1} else {0}),
+                                    6 => Some(if v1 < v2 {1} else {0}),
+                                    7 => Some(if v1 == v2 {1} else {0}),
+                                    _ => None
+                                }
+                            _ => None
+                        }
                 }
             }
-            let value = bin_to_u32(&bits.concat());
-            println!("Litteral {}", value);
-            value
-        } else { // Operator
-            let lengthtype = packet[6];
-            let mut values = Vec::new();
-            if lengthtype {
-                let count = bin_to_u32(&packet[7..18]);
-                s = 18;
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ErrorKind {
+    InvalidHex,
+    Truncated,
+    // Subpackets of a length type 0 operator overrunning the announced length
+    BadLength { announced: usize },
+    InvalidArity { type_id: u8, count: usize },
+    LiteralOverflow,
+    TrailingBits
+}
+
+/* A decoding failure, located by the offset in bits from the start of the
+   transmission of the field or packet at fault. */
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: ErrorKind
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            ErrorKind::InvalidHex =>
+                write!(f, "invalid hexadecimal digit")?,
+            ErrorKind::Truncated =>
+                write!(f, "truncated packet")?,
+            ErrorKind::BadLength { announced } =>
+                write!(f, "subpackets overrun their length of {} bits", announced)?,
+            ErrorKind::InvalidArity { type_id, count } =>
+                write!(f, "operator {} applied to {} operands", operator_name(type_id), count)?,
+            ErrorKind::LiteralOverflow =>
+                write!(f, "literal does not fit in 64 bits")?,
+            ErrorKind::TrailingBits =>
+                write!(f, "non-zero bits after the outermost packet")?
+        }
+        write!(f, " at bit {}", self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn read(reader: &mut BitReader, n: usize) -> Result<u64, DecodeError> {
+    reader.read_bits(n).ok_or(DecodeError { offset: reader.end(), kind: ErrorKind::Truncated })
+}
+
+/* Decodes the packet at the position of the reader, leaving the reader just
+   after it. */
+pub fn decode(reader: &mut BitReader) -> Result<Packet, DecodeError> {
+    let start = reader.position();
+    let version = read(reader, 3)? as u8;
+    let type_id = read(reader, 3)? as u8;
+
+    if type_id == 4 { // Literal value
+        let mut value: u64 = 0;
+        loop {
+            let offset = reader.position();
+            let group = read(reader, 5)?;
+            if value >> 60 != 0 {
+                return Err(DecodeError { offset, kind: ErrorKind::LiteralOverflow });
+            }
+            value = value << 4 | group & 0xF;
+            if group & 0x10 == 0 {
+                break;
+            }
+        }
+        Ok(Packet::Literal { version, value })
+    } else { // Operator
+        let mut children = Vec::new();
+        let length_type =
+            if read(reader, 1)? == 1 {
+                let count = read(reader, 11)?;
                 for _ in 1..=count {
-                    let (sversion, value, tail) = parse_packet(&packet[s..]);
-                    s += tail;
-                    sum += sversion;
-                    values.push(value);
+                    children.push(decode(reader)?);
                 }
+                LengthType::Count
             }
             else {
-                let size = bin_to_u32(&packet[7..22]) as usize;
-                s = 22;
-                while s < 22 + size {
-                    let (sversion, value, tail) = parse_packet(&packet[s..]);
-                    s += tail;
-                    sum += sversion;
-                    values.push(value);
+                let size = read(reader, 15)? as usize;
+                let mut sub = reader.sub_reader(size)
+                    .ok_or(DecodeError { offset: reader.end(), kind: ErrorKind::Truncated })?;
+                while sub.remaining() > 0 {
+                    match decode(&mut sub) {
+                        Ok(child) => children.push(child),
+                        // Running out of the announced bits, rather than of the transmission
+                        Err(DecodeError { offset, kind: ErrorKind::Truncated })
+                            if offset == sub.end() && reader.remaining() > 0 => {
+                            let kind = ErrorKind::BadLength { announced: size };
+                            return Err(DecodeError { offset: start, kind });
+                        }
+                        Err(e) => return Err(e)
+                    }
+                }
+                LengthType::Bits
+            };
+        let valid = if type_id >= 5 { children.len() == 2 } else { !children.is_empty() };
+        if !valid {
+            let kind = ErrorKind::InvalidArity { type_id, count: children.len() };
+            return Err(DecodeError { offset: start, kind });
+        }
+        Ok(Packet::Operator { version, type_id, length_type, children })
+    }
+}
+
+/* A packet that has no BITS encoding: a header field out of its 3 bits, an
+   operator with the type of literals, or a length field that cannot hold the
+   size of the subpackets. */
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum EncodeError {
+    InvalidVersion { version: u8 },
+    InvalidTypeId { type_id: u8 },
+    PayloadTooLong { bits: usize },
+    TooManySubpackets { count: usize }
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EncodeError::InvalidVersion { version } =>
+                write!(f, "version {} does not fit in 3 bits", version),
+            EncodeError::InvalidTypeId { type_id } =>
+                write!(f, "{} is not an operator type", type_id),
+            EncodeError::PayloadTooLong { bits } =>
+                write!(f, "{} bits of subpackets do not fit in a 15-bit length", bits),
+            EncodeError::TooManySubpackets { count } =>
+                write!(f, "{} subpackets do not fit in an 11-bit count", count)
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+fn push_bits(bits: &mut Vec<bool>, x: u64, n: usize) {
+    bits.extend((0..n).rev().map(|i| (x >> i) & 1 == 1));
+}
+
+fn encode_bits(packet: &Packet, bits: &mut Vec<bool>) -> Result<(), EncodeError> {
+    if packet.version() > 7 {
+        return Err(EncodeError::InvalidVersion { version: packet.version() });
+    }
+    if let Packet::Operator { type_id, .. } = packet {
+        if *type_id > 7 || *type_id == 4 {
+            return Err(EncodeError::InvalidTypeId { type_id: *type_id });
+        }
+    }
+    push_bits(bits, packet.version() as u64, 3);
+    match packet {
+        Packet::Literal { value, .. } => {
+            push_bits(bits, 4, 3);
+            let groups = std::cmp::max(1, (64 - value.leading_zeros() as usize).div_ceil(4));
+            for i in (0..groups).rev() {
+                bits.push(i > 0);
+                push_bits(bits, value >> (4 * i), 4);
+            }
+        }
+        Packet::Operator { type_id, length_type, children, .. } => {
+            push_bits(bits, *type_id as u64, 3);
+            let mut payload = Vec::new();
+            for child in children {
+                encode_bits(child, &mut payload)?;
+            }
+            match length_type {
+                LengthType::Bits => {
+                    if payload.len() >= 1 << 15 {
+                        return Err(EncodeError::PayloadTooLong { bits: payload.len() });
+                    }
+                    bits.push(false);
+                    push_bits(bits, payload.len() as u64, 15);
                 }
-                if s != 22 + size {
-                    panic!("Subpackets sizes do not match");
+                LengthType::Count => {
+                    if children.len() >= 1 << 11 {
+                        return Err(EncodeError::TooManySubpackets { count: children.len() });
+                    }
+                    bits.push(true);
+                    push_bits(bits, children.len() as u64, 11);
                 }
             }
+            bits.append(&mut payload);
+        }
+    }
+    Ok(())
+}
 
-            let (op, result) =
-                match typ {
-                    0 => ("+", values.iter().sum()),
-                    1 => ("*", values.iter().product()),
-                    2 => ("min", *values.iter().min().unwrap()),
-                    3 => ("max", *values.iter().max().unwrap()),
-                    _ =>
-                        match values.as_slice() {
-                            [v1,v2] =>
-                                match typ {
-                                    5 => (">", if v1 > v2 {1} else {0}),
-                                    6 => ("<", if v1 < v2 {1} else {0}),
-                                    7 => ("=", if v1 == v2 {1} else {0}),
-                                    _ => panic!() // typ is alwas < 8
-                                }
-                            _ => panic!("Comparison must be between two values")
-                        }                   
-                };
-            println!("{}{:?} -> {}", op, values, result);
-            result
-        };
-  (sum, value, s)
+/* Hexadecimal transmission of the packet, padded with zeros to whole bytes. */
+pub fn encode(packet: &Packet) -> Result<String, EncodeError> {
+    let mut bits = Vec::new();
+    encode_bits(packet, &mut bits)?;
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+    Ok(bits.chunks(4)
+        .map(|digit| char::from_digit(bin_to_u32(digit) as u32, 16).unwrap().to_ascii_uppercase())
+        .collect())
 }
 
+pub fn compile(expression: &str) -> Option<Packet> {
+    parser::expression(expression.as_bytes()).ok().map(|(_,packet)| packet)
+}
 
-pub fn solve(input: &[u8]) -> (u64,u64) {
-    let (_,packet) = parser::parse(input).unwrap();
-    let (sversion,value,tail) = parse_packet(&packet);
-    if packet[tail..].iter().any(|&b| b) {
-        panic!("Tail is not 0: {:?}", &packet[tail..]);
+pub fn decode_transmission(input: &[u8]) -> Result<Packet, DecodeError> {
+    let (bytes, len) = match parser::parse(input) {
+        Ok((_,transmission)) => transmission,
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) =>
+            return Err(DecodeError { offset: 4 * (input.len() - e.input.len()), kind: ErrorKind::InvalidHex }),
+        Err(nom::Err::Incomplete(_)) =>
+            return Err(DecodeError { offset: 4 * input.len(), kind: ErrorKind::InvalidHex })
+    };
+    let mut reader = BitReader::new(&bytes, len);
+    let root = decode(&mut reader)?;
+    while reader.remaining() > 0 {
+        let offset = reader.position();
+        if reader.read_bits(1) == Some(1) {
+            return Err(DecodeError { offset, kind: ErrorKind::TrailingBits });
+        }
     }
-    (sversion,value)
+    Ok(root)
+}
+
+pub fn solve(input: &[u8]) -> (u64,u64) {
+    let packet = decode_transmission(input).unwrap();
+    (packet.version_sum(),packet.evaluate().unwrap())
 }
 
 
@@ -211,3 +551,161 @@ fn test16_14() {
     let solution = solve(include_bytes!("../inputs/day16.14"));
     assert_eq!(solution, (951,902198718880));
 }
+
+#[test]
+fn test16_packets() {
+    assert_eq!(decode_transmission(include_bytes!("../inputs/day16.0")).unwrap(),
+        Packet::Literal { version: 6, value: 2021 });
+    assert_eq!(decode_transmission(include_bytes!("../inputs/day16.1")).unwrap(),
+        Packet::Operator { version: 1, type_id: 6, length_type: LengthType::Bits, children: vec![
+            Packet::Literal { version: 6, value: 10 },
+            Packet::Literal { version: 2, value: 20 }
+        ]});
+    let packet = decode_transmission(b"EE00D40C823060").unwrap();
+    assert_eq!(packet.version(), 7);
+    assert!(matches!(packet, Packet::Operator { type_id: 3, length_type: LengthType::Count, .. }));
+    assert_eq!(packet.to_string(), "(max 1 2 3)");
+    assert_eq!(decode_transmission(include_bytes!("../inputs/day16.2")).unwrap().to_string(), "(min (min (min 15)))");
+    assert_eq!(decode_transmission(include_bytes!("../inputs/day16.13")).unwrap().to_string(), "(= (+ 1 3) (* 2 2))");
+}
+
+#[cfg(test)]
+fn random_packet(rng: &mut crate::random::Rng, depth: u32) -> Packet {
+    let mut next = |bound: u64| rng.below(bound);
+    let version = next(8) as u8;
+    let type_id = next(8) as u8;
+    if type_id == 4 || depth == 0 {
+        let value = next(u64::MAX) >> next(64);
+        return Packet::Literal { version, value };
+    }
+    let length_type = if next(2) == 0 { LengthType::Bits } else { LengthType::Count };
+    let count = if type_id >= 5 { 2 } else { 1 + next(4) };
+    let children = (0..count).map(|_| random_packet(rng, depth - 1)).collect();
+    Packet::Operator { version, type_id, length_type, children }
+}
+
+#[test]
+fn test16_encode() {
+    let inputs: [&[u8] ; 15] = [
+        include_bytes!("../inputs/day16.0"),
+        include_bytes!("../inputs/day16.1"),
+        include_bytes!("../inputs/day16.2"),
+        include_bytes!("../inputs/day16.3"),
+        include_bytes!("../inputs/day16.4"),
+        include_bytes!("../inputs/day16.5"),
+        include_bytes!("../inputs/day16.6"),
+        include_bytes!("../inputs/day16.7"),
+        include_bytes!("../inputs/day16.8"),
+        include_bytes!("../inputs/day16.9"),
+        include_bytes!("../inputs/day16.10"),
+        include_bytes!("../inputs/day16.11"),
+        include_bytes!("../inputs/day16.12"),
+        include_bytes!("../inputs/day16.13"),
+        include_bytes!("../inputs/day16.14")
+    ];
+    for input in inputs {
+        let packet = decode_transmission(input).unwrap();
+        assert_eq!(decode_transmission(encode(&packet).unwrap().as_bytes()).unwrap(), packet);
+    }
+    assert_eq!(encode(&decode_transmission(b"D2FE28").unwrap()).unwrap(), "D2FE28");
+    assert_eq!(encode(&decode_transmission(b"EE00D40C823060").unwrap()).unwrap(), "EE00D40C823060");
+
+    let mut rng = crate::random::Rng::new(0x2545f4914f6cdd1d);
+    for _ in 0..1000 {
+        let packet = random_packet(&mut rng, 4);
+        assert_eq!(decode_transmission(encode(&packet).unwrap().as_bytes()).unwrap(), packet);
+    }
+
+    // 3000 literals of 11 bits: too many to count, too long to measure
+    let zeros = vec!["0" ; 3000].join(",");
+    let packet = compile(&format!("max({})", zeros)).unwrap();
+    assert_eq!(encode(&packet), Err(EncodeError::PayloadTooLong { bits: 33000 }));
+    let Packet::Operator { children, .. } = packet else { unreachable!() };
+    let packet = Packet::Operator { version: 0, type_id: 3, length_type: LengthType::Count, children };
+    assert_eq!(encode(&packet), Err(EncodeError::TooManySubpackets { count: 3000 }));
+    assert_eq!(encode(&packet).unwrap_err().to_string(), "3000 subpackets do not fit in an 11-bit count");
+
+    // Header fields that 3 bits cannot carry
+    let literal = Packet::Literal { version: 9, value: 1 };
+    assert_eq!(encode(&literal), Err(EncodeError::InvalidVersion { version: 9 }));
+    let operator = |type_id| Packet::Operator { version: 0, type_id, length_type: LengthType::Count,
+        children: vec![Packet::Literal { version: 0, value: 1 }] };
+    assert_eq!(encode(&operator(4)), Err(EncodeError::InvalidTypeId { type_id: 4 }));
+    assert_eq!(encode(&operator(8)), Err(EncodeError::InvalidTypeId { type_id: 8 }));
+    let nested = Packet::Operator { version: 1, type_id: 0, length_type: LengthType::Bits,
+        children: vec![literal] };
+    assert_eq!(encode(&nested), Err(EncodeError::InvalidVersion { version: 9 }));
+}
+
+#[test]
+fn test16_compile() {
+    let packet = compile("min(1, 2*3) > 4").unwrap();
+    assert_eq!(packet.to_string(), "(> (min 1 (* 2 3)) 4)");
+    assert_eq!(solve(encode(&packet).unwrap().as_bytes()), (0, 0));
+    let packet = compile("(1 + 2) * 3 + max(4, 5 == 5, 7) * 2").unwrap();
+    assert_eq!(packet.to_string(), "(+ (* (+ 1 2) 3) (* (max 4 (= 5 5) 7) 2))");
+    assert_eq!(decode_transmission(encode(&packet).unwrap().as_bytes()).unwrap().evaluate(), Some(23));
+    assert_eq!(compile("2 < 1 + 2").unwrap().evaluate(), Some(1));
+    assert_eq!(compile("1 +"), None);
+    assert_eq!(compile("1 < 2 < 3"), None);
+}
+
+#[test]
+fn test16_errors() {
+    let error = |offset, kind| Err(DecodeError { offset, kind });
+    assert_eq!(decode_transmission(b"D2FE2"), error(20, ErrorKind::Truncated));
+    assert_eq!(decode_transmission(b"D2FG28"), error(12, ErrorKind::InvalidHex));
+    assert_eq!(decode_transmission(b"D2FE29"), error(23, ErrorKind::TrailingBits));
+    // (< 10 20) announcing 26 bits of subpackets instead of 27
+    assert_eq!(decode_transmission(b"38006B45291200"),
+        error(0, ErrorKind::BadLength { announced: 26 }));
+    // (< 10 20 30) and (max) from the encoder
+    let three = Packet::Operator { version: 0, type_id: 6, length_type: LengthType::Count,
+        children: (1..=3).map(|i| Packet::Literal { version: 0, value: 10 * i }).collect() };
+    assert_eq!(decode_transmission(encode(&three).unwrap().as_bytes()),
+        error(0, ErrorKind::InvalidArity { type_id: 6, count: 3 }));
+    assert_eq!(three.evaluate(), None);
+    let empty = Packet::Operator { version: 0, type_id: 3, length_type: LengthType::Bits, children: vec![] };
+    assert_eq!(decode_transmission(encode(&empty).unwrap().as_bytes()),
+        error(0, ErrorKind::InvalidArity { type_id: 3, count: 0 }));
+
+    // 17 groups of 4 bits
+    assert_eq!(decode_transmission(b"12318C6318C6318C6318C420"), error(86, ErrorKind::LiteralOverflow));
+    let max = Packet::Literal { version: 0, value: u64::MAX };
+    assert_eq!(decode_transmission(encode(&max).unwrap().as_bytes()).unwrap(), max);
+    let overflow = compile("18446744073709551615 + 1").unwrap();
+    assert_eq!(decode_transmission(encode(&overflow).unwrap().as_bytes()).unwrap().evaluate(), None);
+    assert_eq!(compile("4294967296 * 4294967295").unwrap().evaluate(), Some(u64::MAX - 4294967295));
+
+    let message = decode_transmission(b"D2FE2").unwrap_err().to_string();
+    assert_eq!(message, "truncated packet at bit 20");
+}
+
+#[test]
+fn test16_bit_reader() {
+    let bytes = [0b1011_0011, 0b1000_1111, 0b0101_0101];
+    let mut reader = BitReader::new(&bytes, 22);
+    assert_eq!(reader.read_bits(3), Some(0b101));
+    assert_eq!(reader.read_bits(7), Some(0b100_1110));
+    assert_eq!(reader.position(), 10);
+    let mut sub = reader.sub_reader(5).unwrap();
+    assert_eq!(reader.position(), 15);
+    assert_eq!(sub.remaining(), 5);
+    assert_eq!(sub.read_bits(6), None);
+    assert_eq!(sub.read_bits(5), Some(0b00111));
+    assert_eq!(sub.read_bits(1), None);
+    assert!(reader.sub_reader(8).is_none());
+    assert_eq!(reader.read_bits(7), Some(0b101_0101));
+    assert_eq!(reader.remaining(), 0);
+
+    let bytes = [0xA5; 9];
+    let mut reader = BitReader::new(&bytes, 72);
+    assert_eq!(reader.read_bits(4), Some(0xA));
+    assert_eq!(reader.read_bits(64), Some(0x5A5A5A5A5A5A5A5A));
+    assert_eq!(reader.read_bits(0), Some(0));
+
+    // An odd number of digits leaves half of the last byte unread
+    assert_eq!(decode_transmission(b"D2FE28").unwrap(), decode_transmission(b"D2FE280").unwrap());
+    assert_eq!(decode_transmission(b"D2FE2801"),
+        Err(DecodeError { offset: 31, kind: ErrorKind::TrailingBits }));
+}
@@ -1,22 +1,63 @@
+#[derive(Debug,Clone,PartialEq,Eq)]
 pub struct Rect {x1: i32, x2: i32, y1: i32, y2: i32}
 
+impl Rect {
+    /* The bounds are inclusive, and may be given in either order. */
+    pub fn new(x1: i32, x2: i32, y1: i32, y2: i32) -> Self {
+        use std::cmp::{min,max};
+        Rect { x1: min(x1, x2), x2: max(x1, x2), y1: min(y1, y2), y2: max(y1, y2) }
+    }
+}
+
+mod parser {
+    use nom::{
+        IResult, character::complete::*, bytes::complete::*,
+        combinator::*, sequence::* };
+    use super::*;
+
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Rect> {
+        let (input, (_, x1, _, x2, _, y1, _, y2, _)) =
+            all_consuming(tuple((
+                tag("target area: x="), i32, tag(".."), i32,
+                tag(", y="), i32, tag(".."), i32,
+                multispace0)))(input)?;
+        Ok((input, Rect::new(x1, x2, y1, y2)))
+    }
+}
+
 pub fn inside(target: &Rect, x: i32, y: i32) -> bool {
     x >= target.x1 && x <= target.x2 && y >= target.y1 && y <= target.y2
 }
 
+pub struct Trajectory {
+    // Position after each step, starting from the launcher at (0,0)
+    pub positions: Vec<(i32,i32)>,
+    // First step at which the probe is within the target
+    pub hit: Option<usize>
+}
+
+/* Follows the probe until it can no longer reach the target. */
 #[allow(clippy::comparison_chain)]
-pub fn simulate(target: &Rect, vx0 : i32, vy0: i32) -> Option<i32> {
+pub fn trajectory(target: &Rect, vx0 : i32, vy0: i32) -> Trajectory {
     let mut x = 0;
     let mut y = 0;
     let mut vx = vx0;
     let mut vy = vy0;
-    let mut maxy = 0;
+    let mut positions = Vec::new();
+    let mut hit = None;
 
-    while x <= target.x2 && y >= target.y1 {
-        maxy = std::cmp::max(maxy, y);
+    loop {
+        if hit.is_none() && inside(target, x, y) {
+            hit = Some(positions.len());
+        }
+        positions.push((x, y));
 
-        if inside(target, x, y) {
-            return Some(maxy);
+        // Stop once the probe is falling below the target, or moving
+        // away from it horizontally, or stalled beside it
+        if y < target.y1 && vy < 0 ||
+            vx >= 0 && x > target.x2 ||
+            vx <= 0 && x < target.x1 {
+            return Trajectory { positions, hit };
         }
 
         x += vx;
@@ -28,16 +69,29 @@ pub fn simulate(target: &Rect, vx0 : i32, vy0: i32) -> Option<i32> {
         }
         vy -= 1;
     }
-    None
+}
+
+/* The highest point reached up to the first hit, if any. */
+pub fn simulate(target: &Rect, vx0 : i32, vy0: i32) -> Option<i32> {
+    let Trajectory { positions, hit } = trajectory(target, vx0, vy0);
+    positions[..=hit?].iter().map(|&(_,y)| y).max()
 }
 
 
-pub fn solve(target: &Rect) -> (i32,u32) {
+/* Faster probes either overshoot the target at the first step, or only come
+   back to it at height 0 and are too slow horizontally to still be moving
+   by then. */
+fn velocity_bound(target: &Rect) -> i32 {
+    [target.x1, target.x2, target.y1, target.y2].iter().map(|c| c.abs()).max().unwrap()
+}
+
+pub fn brute_force_solve(target: &Rect) -> (i32,u32) {
     let mut count: u32 = 0;
     let mut maxy: i32 = 0;
 
-    for vx in 1..=target.x2 {
-        for vy in target.y1..=-target.y1 {
+    let vy_bound = velocity_bound(target);
+    for vx in std::cmp::min(target.x1, 0)..=std::cmp::max(target.x2, 0) {
+        for vy in -vy_bound..=vy_bound {
             if let Some(y) = simulate(target, vx, vy) {
                 maxy = std::cmp::max(maxy, y);
                 count += 1;
@@ -48,6 +102,114 @@ pub fn solve(target: &Rect) -> (i32,u32) {
     (maxy, count)
 }
 
+fn triangle(v: i64) -> i64 {
+    v * (v + 1) / 2
+}
+
+fn div_ceil(a: i64, n: i64) -> i64 {
+    -(-a).div_euclid(n)
+}
+
+/* Positions along one axis after n steps. Vertically, y(n) = n.vy - T(n-1)
+   where T is the triangle numbers. Horizontally, drag stops the probe after
+   |vx| steps, so x(n) = n.vx - T(n-1) while |vx| >= n and sign(vx).T(|vx|)
+   afterwards. For a fixed n, both are nondecreasing in the velocity, so the
+   velocities landing in a range at step n form an interval. */
+fn vy_range(n: i64, y1: i64, y2: i64) -> (i64,i64) {
+    let t = triangle(n - 1);
+    (div_ceil(y1 + t, n), (y2 + t).div_euclid(n))
+}
+
+// Smallest v >= 0 such that T(v) >= b
+fn min_triangle(b: i64) -> i64 {
+    let mut v = ((((8 * b.max(0) + 1) as f64).sqrt() - 1.0) / 2.0) as i64;
+    while triangle(v) < b {
+        v += 1;
+    }
+    while v > 0 && triangle(v - 1) >= b {
+        v -= 1;
+    }
+    v
+}
+
+// Smallest vx >= 0 such that x(n) >= b, for b > 0
+fn min_vx(n: i64, b: i64) -> i64 {
+    let v = min_triangle(b);
+    if v <= n { v } else { div_ceil(b + triangle(n - 1), n) }
+}
+
+// Largest vx >= 0 such that x(n) <= b, for b >= 0
+fn max_vx(n: i64, b: i64) -> i64 {
+    let v = min_triangle(b + 1) - 1;
+    if v < n { v } else { (b + triangle(n - 1)).div_euclid(n) }
+}
+
+// Whether some probe stops moving horizontally within x1..x2
+fn stalls_within(x1: i64, x2: i64) -> bool {
+    x2 >= 0 && triangle(min_triangle(x1)) <= x2 ||
+        x1 <= 0 && triangle(min_triangle(-x2)) <= -x1
+}
+
+fn vx_range(n: i64, x1: i64, x2: i64) -> (i64,i64) {
+    let lo = if x1 > 0 { min_vx(n, x1) } else { -max_vx(n, -x1) };
+    let hi = if x2 >= 0 { max_vx(n, x2) } else { -min_vx(n, -x2) };
+    (lo, hi)
+}
+
+pub struct Launches {
+    pub velocities: std::collections::BTreeSet<(i32,i32)>,
+    // Highest point reached on the way to the target
+    pub max_apex: i32,
+    // Fewest steps to reach the target, with the first velocity doing so
+    pub fastest: (u32,(i32,i32))
+}
+
+/* All the velocities hitting the target, wherever it lies, computed step
+   count by step count. None if there are infinitely many: when the target
+   spans the launcher's height and a probe can stop moving above it, as it
+   comes back down to height 0 however high it was launched. */
+pub fn analytic_solve(target: &Rect) -> Option<Launches> {
+    let (x1, x2) = (target.x1 as i64, target.x2 as i64);
+    let (y1, y2) = (target.y1 as i64, target.y2 as i64);
+    if y1 <= 0 && 0 <= y2 && stalls_within(x1, x2) {
+        return None;
+    }
+
+    // Otherwise a hit needs |vy| <= bound, and the probe is below the target
+    // for good from step 2 * bound + 2 on
+    let bound = velocity_bound(target) as i64;
+    let mut velocities = std::collections::BTreeSet::new();
+    let mut max_apex = 0;
+    let mut fastest = None;
+    for n in 1..=(2 * bound + 2) {
+        let (vx1, vx2) = vx_range(n, x1, x2);
+        let (vy1, vy2) = vy_range(n, y1, y2);
+        for vx in vx1..=vx2 {
+            for vy in vy1..=vy2 {
+                if velocities.insert((vx as i32, vy as i32)) {
+                    // Highest point before the first hit, which may come
+                    // on the way up
+                    let k = std::cmp::min(n, std::cmp::max(vy, 0));
+                    max_apex = std::cmp::max(max_apex, k * vy - triangle(k - 1));
+                }
+                fastest.get_or_insert((n as u32, (vx as i32, vy as i32)));
+            }
+        }
+    }
+
+    Some(Launches { velocities, max_apex: max_apex as i32, fastest: fastest.unwrap() })
+}
+
+pub fn solve(target: &Rect) -> (i32,u32) {
+    let launches = analytic_solve(target).expect("Target contains the launcher");
+    (launches.max_apex, launches.velocities.len() as u32)
+}
+
+pub fn solve_input(input: &[u8]) -> (i32,u32) {
+    let (_,target) = parser::parse(input).unwrap();
+    solve(&target)
+}
+
 
 #[test]
 fn test17_0() {
@@ -60,3 +222,71 @@ fn test17_1() {
     let solution = solve(&Rect{x1:81,x2:129,y1:-150,y2:-108});
     assert_eq!(solution, (11175,3540));
 }
+
+#[test]
+fn test17_quadrants() {
+    let mut rng = crate::random::Rng::new(0x2545f4914f6cdd1d);
+    let mut next = |bound: u64| rng.below(bound) as i32;
+    let mut tested = 0;
+    while tested < 200 {
+        let (x1, y1) = (next(61) - 30, next(61) - 30);
+        let target = Rect{x1, x2: x1 + next(10), y1, y2: y1 + next(10)};
+        let Some(launches) = analytic_solve(&target) else {
+            assert!(target.y1 <= 0 && target.y2 >= 0);
+            assert!((-30..=30).any(|vx| simulate(&target, vx, 1000).is_some()));
+            continue;
+        };
+        let mut velocities = std::collections::BTreeSet::new();
+        for vx in -40..=40 {
+            for vy in -40..=40 {
+                if simulate(&target, vx, vy).is_some() {
+                    velocities.insert((vx, vy));
+                }
+            }
+        }
+        assert_eq!(launches.velocities, velocities);
+        assert_eq!((launches.max_apex, velocities.len() as u32), brute_force_solve(&target));
+        assert_eq!(launches.fastest.0, 1);
+        assert!(launches.velocities.contains(&launches.fastest.1));
+        tested += 1;
+    }
+}
+
+#[test]
+fn test17_left_above() {
+    let target = Rect{x1:-30,x2:-20,y1:5,y2:10};
+    let launches = analytic_solve(&target).unwrap();
+    assert!(launches.velocities.iter().all(|&(vx,vy)| vx < 0 && vy > 0));
+    assert_eq!(launches.max_apex, 55);
+    assert_eq!(launches.fastest, (1,(-30,5)));
+    assert_eq!(simulate(&target, -7, 10), Some(55));
+    assert!(analytic_solve(&Rect{x1:-1,x2:1,y1:-5,y2:0}).is_none());
+    assert!(analytic_solve(&Rect{x1:7,x2:9,y1:-5,y2:5}).is_some());
+    assert!(analytic_solve(&Rect{x1:9,x2:10,y1:-5,y2:5}).is_none());
+}
+
+#[test]
+fn test17_parse() {
+    let (_,target) = parser::parse(include_bytes!("../inputs/day17.0")).unwrap();
+    assert_eq!(target, Rect::new(20, 30, -10, -5));
+    assert_eq!(Rect::new(30, 20, -5, -10), target);
+    assert!(parser::parse(b"target area: x=20..30").is_err());
+    assert_eq!(solve_input(include_bytes!("../inputs/day17.0")), (45,112));
+    assert_eq!(solve_input(include_bytes!("../inputs/day17.1")), (11175,3540));
+    let (_,target) = parser::parse(b"target area: x=-30..-20, y=5..10\n").unwrap();
+    assert_eq!(target, Rect::new(-30, -20, 5, 10));
+}
+
+#[test]
+fn test17_trajectory() {
+    let target = Rect::new(20, 30, -10, -5);
+    let t = trajectory(&target, 7, 2);
+    assert_eq!(t.positions, vec![(0,0), (7,2), (13,3), (18,3), (22,2), (25,0), (27,-3), (28,-7), (28,-12)]);
+    assert_eq!(t.hit, Some(7));
+    let t = trajectory(&target, 6, 9);
+    assert_eq!(t.hit, Some(20));
+    assert_eq!(t.positions.iter().map(|&(_,y)| y).max(), Some(45));
+    let t = trajectory(&target, 17, -4);
+    assert_eq!(t.positions, vec![(0,0), (17,-4), (33,-9)]);
+    assert_eq!(t.hit, None);
+}
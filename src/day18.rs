@@ -1,4 +1,4 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SnailNum {
     Regular(i32),
     Pair(Box<SnailNum>, Box<SnailNum>)
@@ -22,6 +22,81 @@ pub fn regular(x: i32) -> SnailNum {
     SnailNum::Regular(x)
 }
 
+impl SnailNum {
+    /* No pair nested inside four pairs and no regular number of 10 or more,
+       i.e. nothing left for a reduction to do. */
+    pub fn is_reduced(&self) -> bool {
+        fn check(sn: &SnailNum, depth: u32) -> bool {
+            match sn {
+                SnailNum::Regular(x) => *x < 10,
+                SnailNum::Pair(sn1, sn2) =>
+                    depth < 4 && check(sn1, depth + 1) && check(sn2, depth + 1)
+            }
+        }
+        check(self, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Syntax { offset: usize },
+    TrailingInput { offset: usize },
+    Unreduced { index: usize, number: SnailNum }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Syntax { offset } =>
+                write!(f, "invalid snailfish number at byte {}", offset),
+            ParseError::TrailingInput { offset } =>
+                write!(f, "unexpected input after snailfish number at byte {}", offset),
+            ParseError::Unreduced { index, number } =>
+                write!(f, "snailfish number #{} is not reduced: {}", index, number)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl std::str::FromStr for SnailNum {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parser::parse_single(s.as_bytes())
+    }
+}
+
+impl std::ops::Add for SnailNum {
+    type Output = SnailNum;
+
+    fn add(self, other: SnailNum) -> SnailNum {
+        add(self, other)
+    }
+}
+
+impl std::ops::Add for &SnailNum {
+    type Output = SnailNum;
+
+    fn add(self, other: &SnailNum) -> SnailNum {
+        add(self.clone(), other.clone())
+    }
+}
+
+/* Snailfish addition has no neutral element, so summing nothing panics. */
+impl std::iter::Sum for SnailNum {
+    fn sum<I: Iterator<Item = SnailNum>>(mut iter: I) -> SnailNum {
+        let first = iter.next().expect("sum of no snailfish numbers");
+        iter.fold(first, add)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a SnailNum> for SnailNum {
+    fn sum<I: Iterator<Item = &'a SnailNum>>(iter: I) -> SnailNum {
+        iter.cloned().sum()
+    }
+}
+
 mod parser {
     use nom::{
         IResult, character::complete::*, bytes::complete::*, multi::*,
@@ -44,6 +119,47 @@ mod parser {
         let (input, _) = all_consuming(multispace0)(input)?;
         Ok((input, numbers))
     }
+
+    /* Like parse, but fails on trailing garbage and on numbers that are not
+       reduced, which no addition could have produced. */
+    pub fn parse_reduced(input: &[u8]) -> Result<Vec<SnailNum>, ParseError> {
+        let numbers = match parse(input) {
+            Ok((_, numbers)) => numbers,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) =>
+                return Err(ParseError::Syntax { offset: input.len() - e.input.len() }),
+            Err(nom::Err::Incomplete(_)) =>
+                return Err(ParseError::Syntax { offset: input.len() })
+        };
+        match numbers.iter().position(|sn| !sn.is_reduced()) {
+            Some(index) => Err(ParseError::Unreduced { index, number: numbers[index].clone() }),
+            None => Ok(numbers)
+        }
+    }
+
+    /* A single reduced number, possibly followed by whitespace */
+    pub fn parse_single(input: &[u8]) -> Result<SnailNum, ParseError> {
+        let offset = |rest: &[u8]| input.len() - rest.len();
+        let (rest, number) = match terminated(snum, multispace0)(input) {
+            Ok(r) => r,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) =>
+                return Err(ParseError::Syntax { offset: offset(e.input) }),
+            Err(nom::Err::Incomplete(_)) =>
+                return Err(ParseError::Syntax { offset: input.len() })
+        };
+        if !rest.is_empty() {
+            Err(ParseError::TrailingInput { offset: offset(rest) })
+        }
+        else if !number.is_reduced() {
+            Err(ParseError::Unreduced { index: 0, number })
+        }
+        else {
+            Ok(number)
+        }
+    }
+
+    pub fn parse_flat(input: &[u8]) -> IResult<&[u8], Vec<FlatSnailNum>> {
+        map(parse, |numbers| numbers.iter().map(FlatSnailNum::from).collect())(input)
+    }
 }
 
 fn add_right(sn: SnailNum, y: i32) -> SnailNum {
@@ -62,27 +178,56 @@ fn add_left(sn: SnailNum, y: i32) -> SnailNum {
     }
 }
 
+/* Which child to descend into from a pair. A path from the root of a number
+   is written as a string of L and R. */
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Side {
+    Left,
+    Right
+}
 
-fn explode(snroot: SnailNum, depth: u32) -> (SnailNum,Option<(i32,i32)>) {
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Action {
+    Addition,
+    Explode(Vec<Side>),
+    Split(Vec<Side>)
+}
+
+fn path_to_string(path: &[Side]) -> String {
+    path.iter().map(|side| match side { Side::Left => 'L', Side::Right => 'R' }).collect()
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Action::Addition => write!(f, "addition"),
+            Action::Explode(path) => write!(f, "explode at {}", path_to_string(path)),
+            Action::Split(path) => write!(f, "split at {}", path_to_string(path))
+        }
+    }
+}
+
+/* On success, path is left pointing at the exploded pair. */
+fn explode(snroot: SnailNum, path: &mut Vec<Side>) -> (SnailNum,Option<(i32,i32)>) {
     match snroot {
         SnailNum::Regular(_) => (snroot, None),
         SnailNum::Pair(sn1, sn2) => {
-            if depth < 4 {
-                let (sn1,explosion) = explode(*sn1, depth + 1);
+            if path.len() < 4 {
+                path.push(Side::Left);
+                let (sn1,explosion) = explode(*sn1, path);
                 if let Some((l,r)) = explosion {
                     let sn2 = if r != 0 { add_left(*sn2, r) } else { *sn2 };
-                    (pair(sn1,sn2),Some((l,0)))
+                    return (pair(sn1,sn2),Some((l,0)));
                 }
-                else {
-                    let (sn2,explosion) = explode(*sn2, depth + 1);
-                    if let Some((l,r)) = explosion {
-                        let sn1 = if l != 0 { add_right(sn1, l) } else { sn1 };
-                        (pair(sn1,sn2),Some((0,r)))
-                    }
-                    else {
-                        (pair(sn1,sn2),None)
-                    }
+                path.pop();
+                path.push(Side::Right);
+                let (sn2,explosion) = explode(*sn2, path);
+                if let Some((l,r)) = explosion {
+                    let sn1 = if l != 0 { add_right(sn1, l) } else { sn1 };
+                    return (pair(sn1,sn2),Some((0,r)));
                 }
+                path.pop();
+                (pair(sn1,sn2),None)
             }
             else if let (SnailNum::Regular(x1),SnailNum::Regular(x2)) = (*sn1,*sn2) {
                 (regular(0),Some((x1,x2)))
@@ -94,8 +239,8 @@ fn explode(snroot: SnailNum, depth: u32) -> (SnailNum,Option<(i32,i32)>) {
     }
 }
 
-
-fn split(sn: SnailNum) -> (SnailNum,bool) {
+/* On success, path is left pointing at the split number. */
+fn split(sn: SnailNum, path: &mut Vec<Side>) -> (SnailNum,bool) {
     match sn {
         SnailNum::Regular(x) => {
             if x < 10 {
@@ -106,56 +251,84 @@ fn split(sn: SnailNum) -> (SnailNum,bool) {
             }
         }
         SnailNum::Pair(sn1, sn2) => {
-            let (sn1,did_split) = split(*sn1);
+            path.push(Side::Left);
+            let (sn1,did_split) = split(*sn1, path);
             if did_split {
-                (SnailNum::Pair(Box::new(sn1),sn2),true)
+                return (SnailNum::Pair(Box::new(sn1),sn2),true);
             }
-            else {
-                let (sn2,did_split) = split(*sn2);
-                if did_split {
-                    (SnailNum::Pair(Box::new(sn1),Box::new(sn2)),true)
-                }
-                else {
-                    (SnailNum::Pair(Box::new(sn1),Box::new(sn2)),false)
-                }
+            path.pop();
+            path.push(Side::Right);
+            let (sn2,did_split) = split(*sn2, path);
+            if !did_split {
+                path.pop();
             }
+            (SnailNum::Pair(Box::new(sn1),Box::new(sn2)),did_split)
+        }
+    }
+}
+
+/* Applies the first applicable reduction action, if any. */
+fn reduce_step(sn: SnailNum) -> (SnailNum,Option<Action>) {
+    let mut path = Vec::new();
+    let (sn,explosion) = explode(sn, &mut path);
+    if explosion.is_some() {
+        return (sn, Some(Action::Explode(path)));
+    }
+    let (sn,did_split) = split(sn, &mut path);
+    if did_split {
+        return (sn, Some(Action::Split(path)));
+    }
+    (sn, None)
+}
+
+/* Iterator over the intermediate numbers of an addition, starting with the
+   unreduced pair and ending with the reduced sum. */
+pub struct ReduceSteps {
+    current: Option<SnailNum>,
+    started: bool
+}
+
+impl Iterator for ReduceSteps {
+    type Item = (Action,SnailNum);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sn = self.current.take()?;
+        if !self.started {
+            self.started = true;
+            self.current = Some(sn.clone());
+            return Some((Action::Addition, sn));
         }
+        let (sn,action) = reduce_step(sn);
+        let action = action?;
+        self.current = Some(sn.clone());
+        Some((action, sn))
     }
 }
 
+pub fn reduce_steps(sn1: SnailNum, sn2: SnailNum) -> ReduceSteps {
+    ReduceSteps { current: Some(pair(sn1, sn2)), started: false }
+}
 
 pub fn add(sn1: SnailNum, sn2: SnailNum) -> SnailNum {
     let mut r = pair(sn1,sn2);
-    //println!("After addition :  {}", r);
     loop {
-        let (sn,explosion) = explode(r, 0);
-        r = sn;
-        if explosion.is_some() {
-            //println!("After explosion : {}", r);
-            continue;
-        }
-        let (sn,did_split) = split(r);
+        let (sn,action) = reduce_step(r);
         r = sn;
-        if did_split {
-            //println!("After split :     {}", r);
-            continue;
+        if action.is_none() {
+            break r;
         }
-        //println!("\n  {}\n+ {}\n= {}", sn1, sn2, r);
-        break r;
-    }    
+    }
 }
 
 pub fn magnitude(sn: &SnailNum) -> i64 {
     match sn {
         SnailNum::Regular(x) => *x as i64,
-        SnailNum::Pair(sn1, sn2) => 3*magnitude(&*sn1) + 2*magnitude(&*sn2)
+        SnailNum::Pair(sn1, sn2) => 3*magnitude(sn1) + 2*magnitude(sn2)
     }
 }
 
 pub fn part1(numbers: &[SnailNum]) -> i64 {
-    let mut iter = numbers.to_owned().into_iter();
-    let first = iter.next().unwrap();
-    let sum = iter.fold(first, add);
+    let sum: SnailNum = numbers.iter().sum();
     magnitude(&sum)
 }
 
@@ -164,8 +337,7 @@ pub fn part2(numbers: &[SnailNum]) -> i64 {
     for (i,sn1) in numbers.iter().enumerate() {
         for (j,sn2) in numbers.iter().enumerate() {
             if i != j {
-                let sum = add(sn1.clone(), sn2.clone());
-                max = std::cmp::max(max, magnitude(&sum));
+                max = std::cmp::max(max, magnitude(&(sn1 + sn2)));
             }
         }
     }
@@ -173,10 +345,141 @@ pub fn part2(numbers: &[SnailNum]) -> i64 {
 }
 
 pub fn solve(input: &[u8]) -> (i64,i64) {
-    let (_,numbers) = parser::parse(input).unwrap();
+    let numbers = parser::parse_reduced(input).unwrap();
     (part1(&numbers),part2(&numbers))
 }
 
+/* The same numbers, stored as the list of their regular numbers from left to
+   right, each with the number of pairs enclosing it. Reduction then works in
+   place on a vector instead of rebuilding trees. */
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct FlatSnailNum {
+    items: Vec<(i32,u32)>
+}
+
+fn flatten(sn: &SnailNum, depth: u32, items: &mut Vec<(i32,u32)>) {
+    match sn {
+        SnailNum::Regular(x) => items.push((*x, depth)),
+        SnailNum::Pair(sn1, sn2) => {
+            flatten(sn1, depth + 1, items);
+            flatten(sn2, depth + 1, items);
+        }
+    }
+}
+
+impl From<&SnailNum> for FlatSnailNum {
+    fn from(sn: &SnailNum) -> Self {
+        let mut items = Vec::new();
+        flatten(sn, 0, &mut items);
+        FlatSnailNum { items }
+    }
+}
+
+fn unflatten(items: &[(i32,u32)], i: &mut usize, depth: u32) -> SnailNum {
+    let (x, d) = items[*i];
+    if d == depth {
+        *i += 1;
+        regular(x)
+    }
+    else {
+        let sn1 = unflatten(items, i, depth + 1);
+        let sn2 = unflatten(items, i, depth + 1);
+        pair(sn1, sn2)
+    }
+}
+
+impl From<&FlatSnailNum> for SnailNum {
+    fn from(sn: &FlatSnailNum) -> Self {
+        unflatten(&sn.items, &mut 0, 0)
+    }
+}
+
+impl std::fmt::Display for FlatSnailNum {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", SnailNum::from(self))
+    }
+}
+
+impl FlatSnailNum {
+    fn explode(&mut self) -> bool {
+        let items = &mut self.items;
+        if let Some(i) = items.iter().position(|&(_,d)| d > 4) {
+            let (l, d) = items[i];
+            let (r, _) = items[i + 1];
+            if i > 0 {
+                items[i - 1].0 += l;
+            }
+            if i + 2 < items.len() {
+                items[i + 2].0 += r;
+            }
+            items[i] = (0, d - 1);
+            items.remove(i + 1);
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn split(&mut self) -> bool {
+        let items = &mut self.items;
+        if let Some(i) = items.iter().position(|&(x,_)| x >= 10) {
+            let (x, d) = items[i];
+            items[i] = (x / 2, d + 1);
+            items.insert(i + 1, (x / 2 + x % 2, d + 1));
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let items = self.items.iter().chain(&other.items)
+            .map(|&(x,d)| (x, d + 1))
+            .collect();
+        let mut r = FlatSnailNum { items };
+        while r.explode() || r.split() {}
+        r
+    }
+
+    pub fn magnitude(&self) -> i64 {
+        let mut stack: Vec<(i64,u32)> = Vec::new();
+        for &(x,d) in &self.items {
+            stack.push((x as i64, d));
+            while stack.len() >= 2 && stack[stack.len() - 1].1 == stack[stack.len() - 2].1 {
+                let (r, d) = stack.pop().unwrap();
+                let (l, _) = stack.pop().unwrap();
+                stack.push((3 * l + 2 * r, d - 1));
+            }
+        }
+        stack[0].0
+    }
+}
+
+pub fn part1_flat(numbers: &[FlatSnailNum]) -> i64 {
+    let (first, rest) = numbers.split_first().unwrap();
+    let sum = rest.iter().fold(first.clone(), |acc, sn| acc.add(sn));
+    sum.magnitude()
+}
+
+pub fn part2_flat(numbers: &[FlatSnailNum]) -> i64 {
+    let mut max = 0;
+    for (i,sn1) in numbers.iter().enumerate() {
+        for (j,sn2) in numbers.iter().enumerate() {
+            if i != j {
+                max = std::cmp::max(max, sn1.add(sn2).magnitude());
+            }
+        }
+    }
+    max
+}
+
+pub fn solve_flat(input: &[u8]) -> (i64,i64) {
+    let (_,numbers) = parser::parse_flat(input).unwrap();
+    (part1_flat(&numbers),part2_flat(&numbers))
+}
+
 
 #[test]
 fn test18_0() {
@@ -219,3 +522,83 @@ fn test18_6() {
     let solution = solve(include_bytes!("../inputs/day18.6"));
     assert_eq!(solution, (3524,4656));
 }
+
+#[test]
+fn test18_flat() {
+    let inputs: [&[u8] ; 7] = [
+        include_bytes!("../inputs/day18.0"),
+        include_bytes!("../inputs/day18.1"),
+        include_bytes!("../inputs/day18.2"),
+        include_bytes!("../inputs/day18.3"),
+        include_bytes!("../inputs/day18.4"),
+        include_bytes!("../inputs/day18.5"),
+        include_bytes!("../inputs/day18.6")
+    ];
+    for input in inputs {
+        let (_,numbers) = parser::parse(input).unwrap();
+        let (_,flat_numbers) = parser::parse_flat(input).unwrap();
+        for (sn, flat) in numbers.iter().zip(&flat_numbers) {
+            assert_eq!(format!("{}", flat), format!("{}", sn));
+            assert_eq!(flat.magnitude(), magnitude(sn));
+        }
+        for (i,sn1) in numbers.iter().enumerate() {
+            for (j,sn2) in numbers.iter().enumerate().skip(i + 1).take(10) {
+                let sum = add(sn1.clone(), sn2.clone());
+                assert_eq!(format!("{}", flat_numbers[i].add(&flat_numbers[j])), format!("{}", sum));
+            }
+        }
+        assert_eq!(solve_flat(input), solve(input));
+    }
+}
+
+#[test]
+fn test18_reduce_steps() {
+    let (_,numbers) = parser::parse(b"[[[[4,3],4],4],[7,[[8,4],9]]]\n[1,1]").unwrap();
+    let steps: Vec<String> = reduce_steps(numbers[0].clone(), numbers[1].clone())
+        .map(|(action, sn)| format!("{}: {}", action, sn))
+        .collect();
+    assert_eq!(steps, vec![
+        "addition: [[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]",
+        "explode at LLLL: [[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+        "explode at LRRL: [[[[0,7],4],[15,[0,13]]],[1,1]]",
+        "split at LRL: [[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+        "split at LRRR: [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+        "explode at LRRR: [[[[0,7],4],[[7,8],[6,0]]],[8,1]]"
+    ]);
+    let (action, last) = reduce_steps(numbers[0].clone(), numbers[1].clone()).last().unwrap();
+    assert_eq!(action, Action::Explode(vec![Side::Left, Side::Right, Side::Right, Side::Right]));
+    assert_eq!(format!("{}", last), format!("{}", add(numbers[0].clone(), numbers[1].clone())));
+}
+
+#[test]
+fn test18_traits() {
+    let a: SnailNum = "[[[[4,3],4],4],[7,[[8,4],9]]]".parse().unwrap();
+    let b: SnailNum = "[1,1]".parse().unwrap();
+    let expected: SnailNum = "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]".parse().unwrap();
+    assert_eq!(&a + &b, expected);
+    assert_eq!(a.clone() + b.clone(), expected);
+    assert_eq!([a, b].iter().sum::<SnailNum>(), expected);
+    assert_eq!(expected, pair(pair(pair(pair(regular(0), regular(7)), regular(4)),
+        pair(pair(regular(7), regular(8)), pair(regular(6), regular(0)))), pair(regular(8), regular(1))));
+
+    let numbers: Vec<SnailNum> = ["[1,1]", "[2,2]", "[3,3]", "[4,4]", "[5,5]", "[6,6]"]
+        .iter().map(|s| s.parse().unwrap()).collect();
+    let sum: SnailNum = numbers.into_iter().sum();
+    assert_eq!(format!("{}", sum), "[[[[5,0],[7,4]],[5,5]],[6,6]]");
+    assert!(sum.is_reduced());
+}
+
+#[test]
+fn test18_unreduced() {
+    assert!(!pair(regular(10), regular(1)).is_reduced());
+    assert_eq!("[[1,2],[[3,4],x]]".parse::<SnailNum>(), Err(ParseError::Syntax { offset: 14 }));
+    assert_eq!("[1,2] [3,4]".parse::<SnailNum>(), Err(ParseError::TrailingInput { offset: 6 }));
+    let err = "[1,2] [3,4]".parse::<SnailNum>().unwrap_err();
+    assert_eq!(err.to_string(), "unexpected input after snailfish number at byte 6");
+    assert_eq!("[1,2]\n".parse::<SnailNum>(), Ok(pair(regular(1), regular(2))));
+    let err = "[[[[[9,8],1],2],3],4]".parse::<SnailNum>().unwrap_err();
+    assert_eq!(err.to_string(), "snailfish number #0 is not reduced: [[[[[9,8],1],2],3],4]");
+    assert_eq!(parser::parse_reduced(b"[1,2]\n[3,[4,12]]\n"), Err(ParseError::Unreduced {
+        index: 1, number: pair(regular(3), pair(regular(4), regular(12)))
+    }));
+}
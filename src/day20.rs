@@ -1,10 +1,66 @@
 use array2d::Array2D;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::netpbm;
 
 #[derive(Debug,PartialEq,Clone,Copy)]
 pub struct Pixel(bool);
+#[derive(Debug,Clone)]
 pub struct Image(Array2D<Pixel>);
+#[derive(Debug)]
 pub struct Enhancement(Vec<Pixel>);
 
+/* An enhancement must map each of the 512 possible 3x3 neighbourhoods. */
+#[derive(Debug,PartialEq,Eq)]
+pub struct BadEnhancementLength(pub usize);
+
+impl std::fmt::Display for BadEnhancementLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "enhancement has {} entries instead of 512", self.0)
+    }
+}
+
+/* Why an input could not be read: the byte offset of a syntax error, or an
+   enhancement of the wrong length. */
+#[derive(Debug,PartialEq,Eq)]
+pub enum ParseError {
+    Syntax { offset: usize },
+    Enhancement(BadEnhancementLength)
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Syntax { offset } =>
+                write!(f, "invalid input at byte {}", offset),
+            ParseError::Enhancement(e) =>
+                write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<BadEnhancementLength> for ParseError {
+    fn from(e: BadEnhancementLength) -> Self {
+        ParseError::Enhancement(e)
+    }
+}
+
+impl TryFrom<Vec<Pixel>> for Enhancement {
+    type Error = BadEnhancementLength;
+
+    fn try_from(pixels: Vec<Pixel>) -> Result<Self, Self::Error> {
+        if pixels.len() == 512 {
+            Ok(Enhancement(pixels))
+        }
+        else {
+            Err(BadEnhancementLength(pixels.len()))
+        }
+    }
+}
+
 impl std::fmt::Display for Pixel {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", if self.0 {"#"} else {"."})
@@ -41,13 +97,32 @@ mod parser {
         Ok((input, Pixel(c == '#')))
     }
 
-    pub fn parse(input: &[u8]) -> IResult<&[u8], (Enhancement, Image)> {
+    fn diagram(input: &[u8]) -> IResult<&[u8], (Vec<Pixel>, Image)> {
         let (input, (enhancement,_,image)) = tuple((
-            count(pixel, 512),
+            many1(pixel),
             multispace1,
             separated_list1(multispace1, many1(pixel))))(input)?;
         let (input, _) = all_consuming(multispace0)(input)?;
-        Ok((input, (Enhancement(enhancement), Image(Array2D::from_rows(&image)))))
+        Ok((input, (enhancement, Image(Array2D::from_rows(&image)))))
+    }
+
+    /* Like diagram, but with the length of the enhancement checked. */
+    pub fn parse(input: &[u8]) -> Result<(Enhancement, Image), ParseError> {
+        let (enhancement, image) = match diagram(input) {
+            Ok((_, diagram)) => diagram,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) =>
+                return Err(ParseError::Syntax { offset: input.len() - e.input.len() }),
+            Err(nom::Err::Incomplete(_)) =>
+                return Err(ParseError::Syntax { offset: input.len() })
+        };
+        Ok((Enhancement::try_from(enhancement)?, image))
+    }
+}
+
+impl Image {
+    /* Writes the image as a PBM file, lit pixels in black. */
+    pub fn write_pbm<W: Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        netpbm::write_pbm(out, self.0.num_columns(), self.0.num_rows(), |i, j| self.0[(i, j)].0)
     }
 }
 
@@ -105,27 +180,320 @@ fn count_pixels(image: &Image) -> u32 {
     c
 }
 
-pub fn solve(input: &[u8]) -> (u32,u32) {
-    let (_,(enhancement,image)) = parser::parse(input).unwrap();
+/* Pixels of an infinite image are all the same far enough from the origin:
+   this is the background. The others are stored either as a window holding
+   all of them, or for very sparse images as the set of their coordinates,
+   given as (row, column) from the top-left corner of the input image. */
+#[derive(Debug,Clone)]
+enum Canvas {
+    Dense { window: Image, origin: (i64,i64) },
+    Sparse(HashSet<(i64,i64)>)
+}
+
+#[derive(Debug,Clone)]
+pub struct InfiniteImage {
+    canvas: Canvas,
+    background: Pixel
+}
+
+impl InfiniteImage {
+    pub fn new(image: Image) -> Self {
+        InfiniteImage {
+            canvas: Canvas::Dense { window: image, origin: (0, 0) },
+            background: Pixel(false)
+        }
+    }
+
+    pub fn background(&self) -> Pixel {
+        self.background
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.canvas, Canvas::Sparse(_))
+    }
+
+    pub fn get(&self, row: i64, col: i64) -> Pixel {
+        match &self.canvas {
+            Canvas::Dense { window, origin } => {
+                let (i, j) = (row - origin.0, col - origin.1);
+                if i < 0 || j < 0 {
+                    return self.background;
+                }
+                *window.0.get(i as usize, j as usize).unwrap_or(&self.background)
+            }
+            Canvas::Sparse(others) =>
+                Pixel(self.background.0 != others.contains(&(row, col)))
+        }
+    }
+
+    // Coordinates of the pixels differing from the background
+    fn others(&self) -> HashSet<(i64,i64)> {
+        match &self.canvas {
+            Canvas::Dense { window, origin } => {
+                let mut others = HashSet::new();
+                for (i,row_iter) in window.0.rows_iter().enumerate() {
+                    for (j,pixel) in row_iter.enumerate() {
+                        if *pixel != self.background {
+                            others.insert((origin.0 + i as i64, origin.1 + j as i64));
+                        }
+                    }
+                }
+                others
+            }
+            Canvas::Sparse(others) => others.clone()
+        }
+    }
+
+    /* Smallest window holding all the pixels differing from the background,
+       with the coordinates of its top-left corner. */
+    pub fn window(&self) -> (Image, (i64,i64)) {
+        let others = self.others();
+        if others.is_empty() {
+            return (Image(Array2D::filled_with(self.background, 0, 0)), (0, 0));
+        }
+        let top = others.iter().map(|p| p.0).min().unwrap();
+        let bottom = others.iter().map(|p| p.0).max().unwrap();
+        let left = others.iter().map(|p| p.1).min().unwrap();
+        let right = others.iter().map(|p| p.1).max().unwrap();
+        let mut array = Array2D::filled_with(
+            self.background,
+            (bottom - top + 1) as usize,
+            (right - left + 1) as usize);
+        for (row, col) in others {
+            let _ = array.set((row - top) as usize, (col - left) as usize, Pixel(!self.background.0));
+        }
+        (Image(array), (top, left))
+    }
+
+    pub fn to_sparse(&self) -> Self {
+        InfiniteImage { canvas: Canvas::Sparse(self.others()), background: self.background }
+    }
+
+    pub fn to_dense(&self) -> Self {
+        let (window, origin) = self.window();
+        InfiniteImage { canvas: Canvas::Dense { window, origin }, background: self.background }
+    }
+
+    fn enhance_once(&self, enhancement: &Enhancement) -> Self {
+        let background = enhancement.0[if self.background.0 { 511 } else { 0 }];
+        let canvas = match &self.canvas {
+            Canvas::Dense { window, origin } => Canvas::Dense {
+                window: enhance(window, enhancement, self.background),
+                origin: (origin.0 - 1, origin.1 - 1)
+            },
+            Canvas::Sparse(others) => {
+                // Only the neighbours of the pixels differing from the
+                // background may differ from the new background
+                let mut candidates = HashSet::new();
+                for &(row, col) in others {
+                    for a in -1..=1 {
+                        for b in -1..=1 {
+                            candidates.insert((row + a, col + b));
+                        }
+                    }
+                }
+                let new_others = candidates.into_iter().filter(|&(row, col)| {
+                    let mut value = 0;
+                    for a in -1..=1 {
+                        for b in -1..=1 {
+                            let pixel = self.background.0 != others.contains(&(row + a, col + b));
+                            value = value * 2 + if pixel {1} else {0};
+                        }
+                    }
+                    enhancement.0[value] != background
+                }).collect();
+                Canvas::Sparse(new_others)
+            }
+        };
+        InfiniteImage { canvas, background }
+    }
+
+    pub fn enhance(&self, enhancement: &Enhancement, steps: usize) -> Self {
+        let mut image = self.clone();
+        for _ in 0..steps {
+            image = image.enhance_once(enhancement);
+        }
+        image
+    }
+
+    /* None when infinitely many pixels are lit. */
+    pub fn count_lit(&self) -> Option<usize> {
+        if self.background.0 {
+            return None;
+        }
+        match &self.canvas {
+            Canvas::Dense { window, .. } => Some(count_pixels(window) as usize),
+            Canvas::Sparse(others) => Some(others.len())
+        }
+    }
+}
+
+impl std::fmt::Display for InfiniteImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.window().0)
+    }
+}
+
+/* Saves the image after each step from 0 to steps as dir/step_NNN.pgm, all
+   drawn on the area the last step reaches. Lit pixels are black and unlit
+   ones white. Beyond the area reached so far, pixels are the background,
+   drawn in dark grey when lit and light grey otherwise. */
+pub fn dump_frames(dir: &Path, image: &Image, enhancement: &Enhancement, steps: usize)
+                   -> io::Result<Vec<PathBuf>> {
+    let (height, width) = (image.0.num_rows() as i64, image.0.num_columns() as i64);
+    let n = steps as i64;
+    let mut current = InfiniteImage::new(image.clone());
+    let mut paths = Vec::new();
+    for k in 0..=n {
+        let path = dir.join(format!("step_{:03}.pgm", k));
+        netpbm::save(&path, |out| {
+            netpbm::write_pgm(out, (width + 2 * n) as usize, (height + 2 * n) as usize, 3, |i, j| {
+                let (row, col) = (i as i64 - n, j as i64 - n);
+                let reached = -k <= row && row < height + k && -k <= col && col < width + k;
+                match (reached, current.get(row, col).0) {
+                    (true, true) => 0,
+                    (false, true) => 1,
+                    (false, false) => 2,
+                    (true, false) => 3
+                }
+            })
+        })?;
+        paths.push(path);
+        current = current.enhance(enhancement, 1);
+    }
+    Ok(paths)
+}
+
+/* Same as dump_frames, for the image and enhancement given by the input. */
+pub fn dump_frames_from_input(dir: &Path, input: &[u8], steps: usize) -> io::Result<Vec<PathBuf>> {
+    let (enhancement,image) = parser::parse(input).unwrap();
+    dump_frames(dir, &image, &enhancement, steps)
+}
+
+/* The enhancement and image of the input. */
+pub fn parse_input(input: &[u8]) -> Result<(Enhancement, Image), ParseError> {
+    parser::parse(input)
+}
+
+/* The input image, before any enhancement. */
+pub fn input_image(input: &[u8]) -> Image {
+    let (_,image) = parser::parse(input).unwrap();
+    image
+}
+
+/* Number of pixels lit after each number of steps. */
+pub fn count_after(input: &[u8], steps: &[usize]) -> Vec<Option<usize>> {
+    let (enhancement,image) = parser::parse(input).unwrap();
+    let mut image = InfiniteImage::new(image);
+    let mut done = 0;
+    let mut counts = Vec::new();
+    for &n in steps {
+        assert!(n >= done, "Steps must be in increasing order");
+        image = image.enhance(&enhancement, n - done);
+        done = n;
+        counts.push(image.count_lit());
+    }
+    counts
+}
 
-    let mut count1 = 0;
-    let mut count2 = 0;
-    let mut enhanced_image = image;
-    let mut infinity = Pixel(false);
+/* Fast path: rows packed 64 pixels to a word, and the neighbourhood index
+   of each pixel obtained from the one on its left by shifting out a column
+   and shifting in the next one. */
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct BitImage {
+    rows: Vec<Vec<u64>>,
+    width: usize,
+    background: bool
+}
 
-    for step in 1..=50 {
-        enhanced_image = enhance(&enhanced_image, &enhancement, infinity);
-        infinity = enhancement.0[if infinity.0 { 511 } else { 0 }];
-        if enhanced_image.0.num_elements() < 400 {
-            println!("After {} steps:\n{}", step, enhanced_image);
+impl From<&Image> for BitImage {
+    fn from(image: &Image) -> Self {
+        let width = image.0.num_columns();
+        let rows = image.0.rows_iter().map(|row_iter| {
+            let mut words = vec![0; width.div_ceil(64)];
+            for (j,pixel) in row_iter.enumerate() {
+                if pixel.0 {
+                    words[j / 64] |= 1 << (j % 64);
+                }
+            }
+            words
+        }).collect();
+        BitImage { rows, width, background: false }
+    }
+}
+
+impl From<&BitImage> for Image {
+    fn from(image: &BitImage) -> Self {
+        let rows: Vec<Vec<Pixel>> = (0..image.rows.len() as i64)
+            .map(|i| (0..image.width as i64).map(|j| Pixel(image.get(i, j))).collect())
+            .collect();
+        Image(Array2D::from_rows(&rows))
+    }
+}
+
+impl BitImage {
+    fn get(&self, i: i64, j: i64) -> bool {
+        match self.rows.get(i as usize) {
+            Some(words) if i >= 0 && j >= 0 && (j as usize) < self.width =>
+                (words[j as usize / 64] >> (j % 64)) & 1 == 1,
+            _ => self.background
         }
-        match step {
-            2 => count1 = count_pixels(&enhanced_image),
-            50 => count2 = count_pixels(&enhanced_image),
-            _ => ()
+    }
+
+    fn enhance_once(&self, table: &[u64; 8]) -> Self {
+        let lookup = |index: usize| (table[index / 64] >> (index % 64)) & 1 == 1;
+        let width = self.width + 2;
+        let height = self.rows.len() + 2;
+        // The neighbourhood of output pixel (i,j) is centered on input pixel
+        // (i-1,j-1), so each index takes in input column j
+        let rows = (0..height as i64).map(|i| {
+            let mut words = vec![0; width.div_ceil(64)];
+            let mut index = if self.background { 0b111_111_111 } else { 0 };
+            for j in 0..width as i64 {
+                let column =
+                    (self.get(i - 2, j) as usize) << 6 |
+                    (self.get(i - 1, j) as usize) << 3 |
+                    self.get(i, j) as usize;
+                index = (index << 1) & 0b110_110_110 | column;
+                if lookup(index) {
+                    words[j as usize / 64] |= 1 << (j % 64);
+                }
+            }
+            words
+        }).collect();
+        let background = lookup(if self.background { 511 } else { 0 });
+        BitImage { rows, width, background }
+    }
+
+    pub fn enhance(&self, enhancement: &Enhancement, steps: usize) -> Self {
+        let mut table = [0; 8];
+        for (index, pixel) in enhancement.0.iter().enumerate() {
+            if pixel.0 {
+                table[index / 64] |= 1 << (index % 64);
+            }
+        }
+        let mut image = self.clone();
+        for _ in 0..steps {
+            image = image.enhance_once(&table);
         }
+        image
     }
 
+    /* None when infinitely many pixels are lit. */
+    pub fn count_lit(&self) -> Option<usize> {
+        if self.background {
+            return None;
+        }
+        Some(self.rows.iter().flatten().map(|word| word.count_ones() as usize).sum())
+    }
+}
+
+pub fn solve(input: &[u8]) -> (u32,u32) {
+    let (enhancement,image) = parser::parse(input).unwrap();
+    let image = BitImage::from(&image).enhance(&enhancement, 2);
+    let count1 = image.count_lit().unwrap() as u32;
+    let count2 = image.enhance(&enhancement, 48).count_lit().unwrap() as u32;
+
     println!("{} pixels lit after 2 steps", count1);
     println!("{} pixels lit after 50 steps", count2);
     (count1,count2)
@@ -142,3 +510,117 @@ fn test20_1() {
     let solution = solve(include_bytes!("../inputs/day20.1"));
     assert_eq!(solution, (5218,15527));
 }
+
+#[test]
+fn test20_sparse() {
+    for input in [&include_bytes!("../inputs/day20.0")[..], &include_bytes!("../inputs/day20.1")[..]] {
+        let (enhancement,image) = parser::parse(input).unwrap();
+        let mut dense = InfiniteImage::new(image);
+        let mut sparse = dense.to_sparse();
+        assert!(sparse.is_sparse());
+        for _ in 0..3 {
+            dense = dense.enhance(&enhancement, 1);
+            sparse = sparse.enhance(&enhancement, 1);
+            assert_eq!(dense.background(), sparse.background());
+            assert_eq!(dense.count_lit(), sparse.count_lit());
+            assert_eq!(dense.to_string(), sparse.to_string());
+            assert_eq!(dense.to_sparse().to_dense().to_string(), dense.to_string());
+            for row in -5..5 {
+                for col in -5..5 {
+                    assert_eq!(dense.get(row, col), sparse.get(row, col));
+                }
+            }
+        }
+    }
+    assert_eq!(count_after(include_bytes!("../inputs/day20.1"), &[0, 1, 2]), vec![Some(4985), None, Some(5218)]);
+
+    // A single pixel which only keeps itself lit, far beyond what a window
+    // growing at each step could hold
+    let mut pixels = vec![Pixel(false); 512];
+    pixels[0b000_010_000] = Pixel(true);
+    let enhancement = Enhancement::try_from(pixels).unwrap();
+    let image = InfiniteImage::new(Image(Array2D::filled_with(Pixel(true), 1, 1))).to_sparse();
+    let image = image.enhance(&enhancement, 10000);
+    assert_eq!(image.count_lit(), Some(1));
+    assert_eq!(image.get(0, 0), Pixel(true));
+}
+
+#[test]
+fn test20_enhancement_length() {
+    assert_eq!(Enhancement::try_from(vec![Pixel(false); 511]).unwrap_err(), BadEnhancementLength(511));
+    assert_eq!(BadEnhancementLength(513).to_string(), "enhancement has 513 entries instead of 512");
+    let input = include_bytes!("../inputs/day20.0");
+    assert!(parse_input(input).is_ok());
+    assert_eq!(parse_input(&input[1..]).unwrap_err(), ParseError::Enhancement(BadEnhancementLength(511)));
+    let mut longer = b"#".to_vec();
+    longer.extend_from_slice(input);
+    let err = parse_input(&longer).unwrap_err();
+    assert_eq!(err.to_string(), "enhancement has 513 entries instead of 512");
+    assert_eq!(parse_input(b"#.#\n\n#x#\n").unwrap_err(), ParseError::Syntax { offset: 6 });
+}
+
+#[test]
+fn test20_bit_parallel() {
+    use std::time::Instant;
+    for input in [&include_bytes!("../inputs/day20.0")[..], &include_bytes!("../inputs/day20.1")[..]] {
+        let (enhancement,image) = parser::parse(input).unwrap();
+        let mut reference = InfiniteImage::new(image.clone());
+        let mut fast = BitImage::from(&image);
+        for step in 1..=3 {
+            reference = reference.enhance(&enhancement, 1);
+            fast = fast.enhance(&enhancement, 1);
+            // The bit image grows by one pixel on each side at each step
+            let window = Image::from(&fast);
+            for i in -2..window.0.num_rows() as i64 + 2 {
+                for j in -2..window.0.num_columns() as i64 + 2 {
+                    assert_eq!(Pixel(fast.get(i, j)), reference.get(i - step, j - step));
+                }
+            }
+            assert_eq!(fast.count_lit(), reference.count_lit());
+        }
+
+        let start = Instant::now();
+        let reference = InfiniteImage::new(image.clone()).enhance(&enhancement, 50).count_lit();
+        let reference_time = start.elapsed();
+
+        let start = Instant::now();
+        let fast = BitImage::from(&image).enhance(&enhancement, 50).count_lit();
+        let fast_time = start.elapsed();
+
+        println!("array: {:?}, bit-parallel: {:?}, speedup: {:.2}",
+            reference_time, fast_time,
+            reference_time.as_secs_f64() / fast_time.as_secs_f64());
+        assert_eq!(fast, reference);
+    }
+}
+
+#[test]
+fn test20_export() {
+    let input = include_bytes!("../inputs/day20.0");
+    let mut out = Vec::new();
+    input_image(input).write_pbm(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "P1\n5 5\n10010\n10000\n11001\n00100\n00111\n");
+
+    let dir = std::env::temp_dir().join(format!("aoc_test_day20_frames_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = dump_frames_from_input(&dir, input, 2).unwrap();
+    assert_eq!(paths.len(), 3);
+    let first = std::fs::read_to_string(&paths[0]).unwrap();
+    assert_eq!(first, [
+        "P2", "9 9", "3",
+        "2 2 2 2 2 2 2 2 2",
+        "2 2 2 2 2 2 2 2 2",
+        "2 2 0 3 3 0 3 2 2",
+        "2 2 0 3 3 3 3 2 2",
+        "2 2 0 0 3 3 0 2 2",
+        "2 2 3 3 0 3 3 2 2",
+        "2 2 3 3 0 0 0 2 2",
+        "2 2 2 2 2 2 2 2 2",
+        "2 2 2 2 2 2 2 2 2", ""].join("\n"));
+    let last = std::fs::read_to_string(&paths[2]).unwrap();
+    let levels: Vec<&str> = last.split_whitespace().skip(4).collect();
+    assert_eq!(levels.len(), 81);
+    assert_eq!(levels.iter().filter(|&&v| v == "0").count(), 35);
+    assert!(levels.iter().all(|&v| v == "0" || v == "3"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
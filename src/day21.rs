@@ -1,5 +1,50 @@
 use std::collections::HashMap;
 
+/* Parameters of the game: the deterministic die is used in part 1 and the
+   Dirac die in part 2, each with its own winning score. */
+#[derive(Debug,Clone)]
+pub struct GameRules {
+    pub board_size: u32,
+    pub players: usize,
+    pub rolls_per_turn: u32,
+    pub deterministic_faces: u32,
+    pub deterministic_target: u32,
+    pub dirac_faces: u32,
+    pub dirac_target: u32
+}
+
+impl GameRules {
+    pub const STANDARD: GameRules = GameRules {
+        board_size: 10,
+        players: 2,
+        rolls_per_turn: 3,
+        deterministic_faces: 100,
+        deterministic_target: 1000,
+        dirac_faces: 3,
+        dirac_target: 21
+    };
+
+    /* Every possible sum of the Dirac rolls of one turn, with the number of
+       universes in which it happens. */
+    pub fn roll_sums(&self) -> Vec<(u32,u64)> {
+        let mut sums = vec![(0,1)];
+        for _ in 0..self.rolls_per_turn {
+            let mut next: Vec<(u32,u64)> = Vec::new();
+            for (sum, count) in sums {
+                for face in 1..=self.dirac_faces {
+                    match next.iter_mut().find(|(s,_)| *s == sum + face) {
+                        Some((_,c)) => *c += count,
+                        None => next.push((sum + face, count))
+                    }
+                }
+            }
+            sums = next;
+        }
+        sums.sort_unstable();
+        sums
+    }
+}
+
 #[derive(Debug,PartialEq,Eq,Clone,Hash)]
 struct Player {
     score: u32,
@@ -8,25 +53,33 @@ struct Player {
 
 #[derive(Debug,PartialEq,Eq,Clone,Hash)]
 struct Game {
-    players: [Player ; 2],
+    players: Vec<Player>,
     current_player: usize
 }
 
-fn init(p1_start: u32, p2_start: u32) -> Game {
+fn init(starts: &[u32]) -> Game {
     Game {
-        players: [
-            Player { space: p1_start, score: 0 },
-            Player { space: p2_start, score: 0 }
-        ],
+        players: starts.iter()
+            .map(|&space| Player { space, score: 0 })
+            .collect(),
         current_player: 0
     }
 }
 
-fn step(game: &mut Game, rolls: &[u32], verbose : bool) -> u32 {
+fn advance(game: &mut Game, rules: &GameRules, total: u32) -> u32 {
     let player = &mut game.players[game.current_player];
 
-    player.space = (player.space + rolls.iter().sum::<u32>() - 1) % 10 + 1;
+    player.space = (player.space + total - 1) % rules.board_size + 1;
     player.score += player.space;
+    player.score
+}
+
+fn next_player(game: &mut Game) {
+    game.current_player = (game.current_player + 1) % game.players.len();
+}
+
+fn step(game: &mut Game, rules: &GameRules, rolls: &[u32], verbose : bool) -> u32 {
+    let score = advance(game, rules, rolls.iter().sum());
 
     if verbose {
         let rolls : Vec<String> = rolls.iter().map(|x| x.to_string()).collect();
@@ -35,14 +88,14 @@ fn step(game: &mut Game, rolls: &[u32], verbose : bool) -> u32 {
              score of {}.",
             game.current_player + 1,
             rolls.join("+"),
-            player.space,
-            player.score);
+            game.players[game.current_player].space,
+            score);
     }
 
-    player.score
+    score
 }
 
-fn part1(initial: &Game) -> u32 {
+fn part1(rules: &GameRules, initial: &Game) -> u32 {
     println!("--- Part 1 ---");
 
     let mut game = initial.clone();
@@ -50,72 +103,205 @@ fn part1(initial: &Game) -> u32 {
     let mut last_roll = 0;
     let mut roll = || {
         roll_count += 1;
-        last_roll = last_roll % 100 + 1;
+        last_roll = last_roll % rules.deterministic_faces + 1;
         last_roll
     };
 
-    let (winner, solution) = loop {
-        let rolls : Vec<u32> = (0..3).map(|_| roll()).collect();
+    let winner = loop {
+        let rolls : Vec<u32> = (0..rules.rolls_per_turn).map(|_| roll()).collect();
 
-        if step(&mut game, &rolls, true) >= 1000 {
-            let low_score = game.players[(game.current_player+1)%2].score;
-            break (game.current_player + 1, low_score * roll_count);
+        if step(&mut game, rules, &rolls, true) >= rules.deterministic_target {
+            break game.current_player;
         }
 
-        game.current_player = (game.current_player + 1) % 2;
+        next_player(&mut game);
     };
 
-    println!("Player {} wins !", winner);
-    println!("Player 1  {} - {}  Player 2",
-        game.players[0].score, game.players[1].score);
+    let low_score = game.players.iter()
+        .enumerate()
+        .filter(|(i,_)| *i != winner)
+        .map(|(_,p)| p.score)
+        .min().unwrap();
+    let solution = low_score * roll_count;
+
+    let scores : Vec<String> = game.players.iter().map(|p| p.score.to_string()).collect();
+    println!("Player {} wins !", winner + 1);
+    println!("Scores: {}", scores.join(" - "));
     println!("{} rolls, solution is {}", roll_count, solution);
 
     solution
 }
 
-fn explore_universes(cache: &mut HashMap<Game,[u64 ; 2]>, game: Game) -> [u64 ; 2] {
+fn explore_universes(
+        rules: &GameRules,
+        roll_sums: &[(u32,u64)],
+        cache: &mut HashMap<Game,Vec<u64>>,
+        game: Game) -> Vec<u64>
+{
     if let Some(r) = cache.get(&game) {
-        return *r;
+        return r.clone();
     }
 
-    let mut wins = [0 ; 2];
-    for r1 in 1..=3 {
-        for r2 in 1..=3 {
-            for r3 in 1..=3 {
-                let mut game = game.clone();
-                let rolls = [r1, r2, r3];
-                if step(&mut game, &rolls, false) >= 21 {
-                    wins[game.current_player] += 1;
-                } else {
-                    game.current_player = (game.current_player + 1) % 2;
-                    let w = explore_universes(cache, game);
-                    wins[0] += w[0];
-                    wins[1] += w[1];
-                }
+    let mut wins = vec![0 ; game.players.len()];
+    for &(total, universes) in roll_sums {
+        let mut game = game.clone();
+        if advance(&mut game, rules, total) >= rules.dirac_target {
+            wins[game.current_player] += universes;
+        } else {
+            next_player(&mut game);
+            let w = explore_universes(rules, roll_sums, cache, game);
+            for (wins, w) in wins.iter_mut().zip(w) {
+                *wins += universes * w;
             }
         }
     }
 
-    cache.insert(game, wins);
+    cache.insert(game, wins.clone());
     wins
 }
 
-fn part2(initial: &Game) -> u64 {
+fn part2(rules: &GameRules, initial: &Game) -> u64 {
     println!("--- Part 2 ---");
 
     let game = initial.clone();
     let mut cache = HashMap::new();
-    let wins = explore_universes(&mut cache, game);
+    let wins = explore_universes(rules, &rules.roll_sums(), &mut cache, game);
 
-    println!("Player 1 wins in {} universes", wins[0]);
-    println!("Player 2 wins in {} universes", wins[1]);
+    for (i,w) in wins.iter().enumerate() {
+        println!("Player {} wins in {} universes", i + 1, w);
+    }
 
     *wins.iter().max().unwrap()
 }
 
+/* Like explore_universes, but splits the wins according to the number of
+   turns left before the end of the game: r[t][p] is the number of universes
+   in which player p wins on the (t+1)-th turn from now. */
+fn explore_turns(
+        rules: &GameRules,
+        roll_sums: &[(u32,u64)],
+        cache: &mut HashMap<Game,Vec<Vec<u64>>>,
+        game: Game) -> Vec<Vec<u64>>
+{
+    if let Some(r) = cache.get(&game) {
+        return r.clone();
+    }
+
+    let players = game.players.len();
+    let mut wins = vec![vec![0 ; players]];
+    for &(total, universes) in roll_sums {
+        let mut game = game.clone();
+        if advance(&mut game, rules, total) >= rules.dirac_target {
+            wins[0][game.current_player] += universes;
+        } else {
+            next_player(&mut game);
+            let w = explore_turns(rules, roll_sums, cache, game);
+            if wins.len() < w.len() + 1 {
+                wins.resize(w.len() + 1, vec![0 ; players]);
+            }
+            for (wins, w) in wins[1..].iter_mut().zip(w) {
+                for (wins, w) in wins.iter_mut().zip(w) {
+                    *wins += universes * w;
+                }
+            }
+        }
+    }
+
+    cache.insert(game, wins.clone());
+    wins
+}
+
+#[derive(Debug,Clone)]
+pub struct StartAnalysis {
+    pub starts: [u32 ; 2],
+    pub wins: [u64 ; 2],
+    /* Universes won by each player at each turn, both players' turns being
+       counted */
+    pub wins_by_turn: Vec<[u64 ; 2]>,
+    pub expected_length: f64
+}
+
+/* Outcome of the Dirac game for every pair of starting spaces */
+pub struct Analysis {
+    board_size: u32,
+    cells: Vec<StartAnalysis>
+}
+
+impl Analysis {
+    pub fn new(rules: &GameRules) -> Analysis {
+        assert_eq!(rules.players, 2);
+        let roll_sums = rules.roll_sums();
+        let universes_per_turn: u64 = roll_sums.iter().map(|(_,u)| u).sum();
+        let mut cache = HashMap::new();
+        let mut cells = Vec::new();
+
+        for p1 in 1..=rules.board_size {
+            for p2 in 1..=rules.board_size {
+                let turns = explore_turns(rules, &roll_sums, &mut cache, init(&[p1, p2]));
+                let wins_by_turn: Vec<[u64 ; 2]> = turns.iter().map(|w| [w[0], w[1]]).collect();
+                let wins = wins_by_turn.iter()
+                    .fold([0, 0], |acc, w| [acc[0] + w[0], acc[1] + w[1]]);
+                let mut expected_length = 0.0;
+                let mut probability = 1.0;
+                for (t, w) in wins_by_turn.iter().enumerate() {
+                    probability /= universes_per_turn as f64;
+                    expected_length += (t + 1) as f64 * (w[0] + w[1]) as f64 * probability;
+                }
+                cells.push(StartAnalysis {
+                    starts: [p1, p2],
+                    wins,
+                    wins_by_turn,
+                    expected_length
+                });
+            }
+        }
+
+        Analysis { board_size: rules.board_size, cells }
+    }
+
+    pub fn get(&self, p1_start: u32, p2_start: u32) -> &StartAnalysis {
+        let size = self.board_size as usize;
+        &self.cells[(p1_start as usize - 1) * size + p2_start as usize - 1]
+    }
+
+    /* Universes won by the given player, indexed by the starting spaces of
+       player 1 then player 2 */
+    pub fn win_matrix(&self, player: usize) -> Vec<Vec<u64>> {
+        self.cells
+            .chunks(self.board_size as usize)
+            .map(|row| row.iter().map(|c| c.wins[player]).collect())
+            .collect()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("p1_start,p2_start,p1_wins,p2_wins,expected_length\n");
+        for c in &self.cells {
+            csv += &format!("{},{},{},{},{}\n",
+                c.starts[0], c.starts[1], c.wins[0], c.wins[1], c.expected_length);
+        }
+        csv
+    }
+
+    pub fn turns_to_csv(&self) -> String {
+        let mut csv = String::from("p1_start,p2_start,turn,p1_wins,p2_wins\n");
+        for c in &self.cells {
+            for (t, w) in c.wins_by_turn.iter().enumerate() {
+                csv += &format!("{},{},{},{},{}\n",
+                    c.starts[0], c.starts[1], t + 1, w[0], w[1]);
+            }
+        }
+        csv
+    }
+}
+
+pub fn solve_with(rules: &GameRules, starts: &[u32]) -> (u32,u64) {
+    assert_eq!(starts.len(), rules.players);
+    let initial = init(starts);
+    (part1(rules, &initial),part2(rules, &initial))
+}
+
 pub fn solve(p1_start: u32, p2_start: u32) -> (u32,u64) {
-    let initial = init(p1_start, p2_start);
-    (part1(&initial),part2(&initial))
+    solve_with(&GameRules::STANDARD, &[p1_start, p2_start])
 }
 
 
@@ -130,3 +316,83 @@ fn test21_1() {
     let solution = solve(6,2);
     assert_eq!(solution, (926610,146854918035875));
 }
+
+#[test]
+fn test21_roll_sums() {
+    assert_eq!(GameRules::STANDARD.roll_sums(),
+        vec![(3,1), (4,3), (5,6), (6,7), (7,6), (8,3), (9,1)]);
+}
+
+#[test]
+fn test21_rules() {
+    // Explores every single roll, as a reference for the grouped sums
+    type Cache = HashMap<(Game,u32,u32),Vec<u64>>;
+    fn explore(rules: &GameRules, cache: &mut Cache, game: Game, rolls: u32, total: u32) -> Vec<u64> {
+        let key = (game.clone(), rolls, total);
+        if let Some(r) = cache.get(&key) {
+            return r.clone();
+        }
+        let mut wins = vec![0 ; game.players.len()];
+        for face in 1..=rules.dirac_faces {
+            let mut game = game.clone();
+            let w =
+                if rolls + 1 < rules.rolls_per_turn {
+                    explore(rules, cache, game, rolls + 1, total + face)
+                }
+                else if advance(&mut game, rules, total + face) >= rules.dirac_target {
+                    let mut w = vec![0 ; game.players.len()];
+                    w[game.current_player] = 1;
+                    w
+                }
+                else {
+                    next_player(&mut game);
+                    explore(rules, cache, game, 0, 0)
+                };
+            for (wins, w) in wins.iter_mut().zip(w) {
+                *wins += w;
+            }
+        }
+        cache.insert(key, wins.clone());
+        wins
+    }
+
+    let rules = GameRules {
+        board_size: 6,
+        players: 3,
+        rolls_per_turn: 2,
+        deterministic_faces: 10,
+        deterministic_target: 100,
+        dirac_faces: 3,
+        dirac_target: 10
+    };
+    let initial = init(&[1, 4, 6]);
+    let mut cache = HashMap::new();
+    let wins = explore_universes(&rules, &rules.roll_sums(), &mut cache, initial.clone());
+    assert_eq!(wins, explore(&rules, &mut HashMap::new(), initial, 0, 0));
+    assert_eq!(solve_with(&rules, &[1, 4, 6]).1, *wins.iter().max().unwrap());
+}
+
+#[test]
+fn test21_analysis() {
+    let analysis = Analysis::new(&GameRules::STANDARD);
+    assert_eq!(analysis.get(4, 8).wins, [444356092776315, 341960390180808]);
+    assert_eq!(analysis.win_matrix(1)[3][7], 341960390180808);
+    assert_eq!(*analysis.get(6, 2).wins.iter().max().unwrap(), 146854918035875);
+
+    for p1 in 1..=10 {
+        for p2 in 1..=10 {
+            let cell = analysis.get(p1, p2);
+            assert_eq!(cell.starts, [p1, p2]);
+            let total: u64 = cell.wins_by_turn.iter().map(|w| w[0] + w[1]).sum();
+            assert_eq!(total, cell.wins[0] + cell.wins[1]);
+            // A player needs at least 3 turns to reach 21
+            assert!(cell.wins_by_turn[..4].iter().all(|w| *w == [0, 0]));
+            assert!(cell.expected_length > 5.0 && cell.expected_length < 20.0);
+        }
+    }
+
+    let csv = analysis.to_csv();
+    assert_eq!(csv.lines().count(), 101);
+    assert!(csv.lines().any(|l| l.starts_with("4,8,444356092776315,341960390180808,")));
+    assert!(analysis.turns_to_csv().lines().count() > 100);
+}
@@ -1,141 +1,15 @@
+use std::collections::{HashMap, BTreeSet};
 use crate::mdarray::*;
+use crate::geometry::{self, Interval, CuboidSet};
 
-#[derive(Debug,Clone)]
-pub struct Interval {
-    l: i32,
-    u: i32
-}
-
-impl std::fmt::Display for Interval {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}..{}", self.l, self.u)
-    }
-}
-
-impl Interval {
-    pub fn inter(&self, other: &Self) -> Option<Self> {
-        use::std::cmp::{min,max};
-        let l = max(self.l, other.l);
-        let u = min(self.u, other.u);
-        if l <= u {
-            Some(Interval {l,u})
-        }
-        else {
-            None
-        }
-    }
-
-    pub fn intersects(&self, other: &Self) -> bool {
-        self.l <= other.u && other.l <= self.u
-    }
-
-    pub fn includes(&self, other: &Self) -> bool {
-        self.l <= other.l && self.u >= other.u
-    }
-
-    pub fn diff_and_inter(self, other: &Self) -> Vec<Self> {
-        if !self.intersects(other) {
-            vec![self]
-        } else {
-            let mut r = Vec::new();
-            let l =
-                if other.l > self.l {
-                    r.push(Interval {l: self.l, u: other.l - 1});
-                    other.l
-                }
-                else {
-                    self.l
-                };
-            let u =
-                if other.u < self.u {
-                    r.push(Interval {l: other.u + 1, u: self.u});
-                    other.u
-                }
-                else {
-                    self.u
-                };
-            r.push(Interval {l, u});
-            r
-        }
-    }
-
-    pub fn cardinal(&self) -> u64 {
-        (self.u + 1 - self.l) as u64
-    }
-}
-
-#[derive(Debug,Clone)]
-pub struct Cuboid {
-    xrange: Interval,
-    yrange: Interval,
-    zrange: Interval
-}
-
-impl std::fmt::Display for Cuboid {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "[{},{},{}]", self.xrange, self.yrange, self.zrange)
-    }
-}
-
-impl Cuboid {
-    pub fn inter(&self, other: &Cuboid) -> Option<Cuboid> {
-        Some(Cuboid {
-            xrange: self.xrange.inter(&other.xrange)?,
-            yrange: self.yrange.inter(&other.yrange)?,
-            zrange: self.zrange.inter(&other.zrange)?
-        })
-    }
-
-    pub fn intersects(&self, other: &Cuboid) -> bool {
-        self.xrange.intersects(&other.xrange) &&
-        self.yrange.intersects(&other.yrange) &&
-        self.zrange.intersects(&other.zrange)
-    }
-
-    pub fn includes(&self, other: &Cuboid) -> bool {
-        self.xrange.includes(&other.xrange) &&
-        self.yrange.includes(&other.yrange) &&
-        self.zrange.includes(&other.zrange)
-    }
-
-    pub fn diff(self: Cuboid, other: &Cuboid) -> Vec<Cuboid> {
-        if self.intersects(other) {
-            let mut r = Vec::new();
-            for xrange in self.xrange.clone().diff_and_inter(&other.xrange) {
-                for yrange in self.yrange.clone().diff_and_inter(&other.yrange) {
-                    for zrange in self.zrange.clone().diff_and_inter(&other.zrange) {
-                        let subcuboid = Cuboid {
-                            xrange: xrange.clone(),
-                            yrange: yrange.clone(),
-                            zrange: zrange.clone()
-                        };
-                        if !other.includes(&subcuboid) {
-                            assert!(!subcuboid.intersects(other));
-                            r.push(subcuboid);
-                        }
-                    }
-                }
-            }
-            r
-        }
-        else {
-            vec![self]
-        }
-    }
-
-    pub fn volume(&self) -> u64 {
-        self.xrange.cardinal() *
-        self.yrange.cardinal() *
-        self.zrange.cardinal()
-    }
-}
+pub type Cuboid = geometry::Cuboid<3>;
 
 pub type State = bool;
 
 #[derive(Debug)]
 pub struct Step {
-    state: State,
-    cuboid: Cuboid
+    pub state: State,
+    pub cuboid: Cuboid
 }
 
 mod parser {
@@ -145,21 +19,23 @@ mod parser {
     };
     use super::*;
 
+    fn range(input: &[u8]) -> IResult<&[u8], Interval> {
+        map(
+            verify(separated_pair(i64, tag(".."), i64), |(l,u)| l <= u),
+            |(l,u)| Interval::new(l, u))(input)
+    }
+
     pub fn step(input: &[u8]) -> IResult<&[u8], Step> {
-        let (input,(state,_,x1,_,x2,_,y1,_,y2,_,z1,_,z2)) = tuple((
+        let (input,(state,_,xrange,_,yrange,_,zrange)) = tuple((
             alt((tag("on"), tag("off"))),
-            tag(" x="), i32, tag(".."), i32,
-            tag(",y="), i32, tag(".."), i32,
-            tag(",z="), i32, tag(".."), i32
+            tag(" x="), range,
+            tag(",y="), range,
+            tag(",z="), range
         ))(input)?;
 
         let step = Step {
             state: state == b"on",
-            cuboid: Cuboid {
-                xrange: Interval{l:x1,u:x2},
-                yrange: Interval{l:y1,u:y2},
-                zrange: Interval{l:z1,u:z2}
-            }
+            cuboid: Cuboid::new([xrange, yrange, zrange])
         };
 
         Ok((input, step))
@@ -173,11 +49,11 @@ mod parser {
 }
 
 pub fn naive_solve(steps: &[Step], area: &Cuboid) -> u64 {
-    let Cuboid {
-            xrange: Interval{l:xx1, u:xx2},
-            yrange: Interval{l:yy1, u:yy2},
-            zrange: Interval{l:zz1, u:zz2}
-        } = area;
+    let [
+            Interval{l:xx1, u:xx2},
+            Interval{l:yy1, u:yy2},
+            Interval{l:zz1, u:zz2}
+        ] = area.ranges;
     let mut cubes = Array3D::new(
         false,
         (xx2 - xx1 + 1) as usize,
@@ -185,11 +61,11 @@ pub fn naive_solve(steps: &[Step], area: &Cuboid) -> u64 {
         (zz2 - zz1 + 1) as usize);
 
     for step in steps {
-        if let Some(Cuboid {
-                xrange: Interval{l:x1,u:x2},
-                yrange: Interval{l:y1,u:y2},
-                zrange: Interval{l:z1,u:z2}
-            }) = step.cuboid.inter(area) {  
+        if let Some(Cuboid { ranges: [
+                Interval{l:x1,u:x2},
+                Interval{l:y1,u:y2},
+                Interval{l:z1,u:z2}
+            ]}) = step.cuboid.inter(area) {  
             for x in x1..=x2 {
                 for y in y1..=y2 {
                     for z in z1..=z2 {
@@ -221,7 +97,7 @@ pub fn smart_solve(steps: &[Step], area: Option<&Cuboid>) -> u64 {
             if let Some(area) = area {
                 step.cuboid.inter(area)
             } else {
-                Some(step.cuboid.clone())
+                Some(step.cuboid)
             };
         if let Some(new_cuboid) = new_cuboid_opt {
             if step.state {
@@ -251,54 +127,229 @@ pub fn smart_solve(steps: &[Step], area: Option<&Cuboid>) -> u64 {
     disjoint_cuboids.iter().map(|c| c.volume()).sum()
 }
 
+/* Inclusion-exclusion: the lit region is described as a signed sum of
+   cuboids. Each step cancels its overlap with every cuboid already counted by
+   adding their intersection with the opposite sign, then counts itself when
+   turning cubes on. Nothing is ever split, and cuboids whose multiplicities
+   cancel out are dropped. */
+pub fn signed_solve(steps: &[Step], area: Option<&Cuboid>) -> u64 {
+    let mut signed_cuboids: HashMap<Cuboid,i64> = HashMap::new();
+
+    for step in steps {
+        let new_cuboid_opt =
+            if let Some(area) = area {
+                step.cuboid.inter(area)
+            } else {
+                Some(step.cuboid)
+            };
+        if let Some(new_cuboid) = new_cuboid_opt {
+            let mut updates = Vec::new();
+            for (c, sign) in &signed_cuboids {
+                if let Some(inter) = c.inter(&new_cuboid) {
+                    updates.push((inter, -sign));
+                }
+            }
+            if step.state {
+                updates.push((new_cuboid, 1));
+            }
+            for (c, sign) in updates {
+                *signed_cuboids.entry(c).or_insert(0) += sign;
+            }
+            signed_cuboids.retain(|_, sign| *sign != 0);
+        }
+    }
+
+    let volume: i64 = signed_cuboids.iter()
+        .map(|(c, sign)| c.volume() as i64 * sign)
+        .sum();
+    volume as u64
+}
+
+/* Sorted distinct coordinates at which some interval starts or stops. Two
+   consecutive boundaries delimit a range which is either included in or
+   disjoint from each of the intervals. */
+fn boundaries<'a>(ranges: impl Iterator<Item = &'a Interval>) -> Vec<i64> {
+    let mut r: Vec<i64> = ranges.flat_map(|i| [i.l, i.u + 1]).collect();
+    r.sort_unstable();
+    r.dedup();
+    r
+}
+
+/* Coordinate compression with a sweep: the x axis is cut into slabs at the
+   step boundaries, each slab into rows at the boundaries of the steps
+   crossing it, and each row into cells at the boundaries of the steps
+   crossing the row. The last step covering a cell decides its state: each
+   row is swept along z, keeping the indices of the steps covering the current
+   cell in an ordered set. With n steps there are O(n^2) rows of O(n) cells,
+   so the whole sweep runs in O(n^3 log n). Returns the number of lit cubes
+   together with the number of cells evaluated. */
+pub fn sweep_solve(steps: &[Step], area: Option<&Cuboid>) -> (u64,usize) {
+    let clipped: Vec<(State,Cuboid)> = steps.iter()
+        .filter_map(|step| {
+            let cuboid =
+                if let Some(area) = area {
+                    step.cuboid.inter(area)?
+                } else {
+                    step.cuboid
+                };
+            Some((step.state, cuboid))
+        })
+        .collect();
+
+    let mut volume = 0;
+    let mut cells = 0;
+    let xs = boundaries(clipped.iter().map(|(_,c)| &c.ranges[0]));
+    for xw in xs.windows(2) {
+        let xactive: Vec<&(State,Cuboid)> = clipped.iter()
+            .filter(|(_,c)| c.ranges[0].contains(xw[0]))
+            .collect();
+        let ys = boundaries(xactive.iter().map(|(_,c)| &c.ranges[1]));
+        for yw in ys.windows(2) {
+            let yactive: Vec<&(State,Cuboid)> = xactive.iter().copied()
+                .filter(|(_,c)| c.ranges[1].contains(yw[0]))
+                .collect();
+            let zs = boundaries(yactive.iter().map(|(_,c)| &c.ranges[2]));
+            let mut events: Vec<(usize,usize)> = yactive.iter().enumerate()
+                .flat_map(|(t,(_,c))| [
+                    (zs.binary_search(&c.ranges[2].l).unwrap(), t),
+                    (zs.binary_search(&(c.ranges[2].u + 1)).unwrap(), t)
+                ])
+                .collect();
+            events.sort_unstable();
+            let mut covering = BTreeSet::new();
+            let mut length = 0;
+            for group in events.chunk_by(|e1, e2| e1.0 == e2.0) {
+                for &(_,t) in group {
+                    if !covering.remove(&t) {
+                        covering.insert(t);
+                    }
+                }
+                let i = group[0].0;
+                if covering.last().is_some_and(|&t| yactive[t].0) {
+                    length += (zs[i + 1] - zs[i]) as u64;
+                }
+            }
+            cells += zs.len().saturating_sub(1);
+            volume += (xw[1] - xw[0]) as u64 * (yw[1] - yw[0]) as u64 * length;
+        }
+    }
+    (volume, cells)
+}
+
+/* Reactor state after a sequence of steps, kept as a set of disjoint lit
+   cuboids so that any region can be queried afterwards. */
+pub struct Reactor {
+    lit: CuboidSet<3>
+}
+
+impl Reactor {
+    pub fn reboot(steps: &[Step]) -> Reactor {
+        let mut lit = CuboidSet::empty();
+        for step in steps {
+            let cuboid = CuboidSet::from(step.cuboid);
+            lit = if step.state { cuboid.union(&lit) } else { lit.diff(&cuboid) };
+        }
+        Reactor { lit }
+    }
+
+    pub fn from_input(input: &[u8]) -> Reactor {
+        let (_,steps) = parser::parse(input).unwrap();
+        Reactor::reboot(&steps)
+    }
+
+    pub fn is_on(&self, x: i64, y: i64, z: i64) -> bool {
+        self.lit.contains(&[x, y, z])
+    }
+
+    pub fn count_on(&self) -> u64 {
+        self.lit.volume()
+    }
+
+    pub fn count_on_in(&self, region: &Cuboid) -> u64 {
+        self.lit.inter(&CuboidSet::from(*region)).volume()
+    }
+
+    pub fn lit_cuboids_in<'a>(&'a self, region: &'a Cuboid) -> impl Iterator<Item = &'a Cuboid> {
+        self.lit.cuboids().iter().filter(move |c| c.intersects(region))
+    }
+}
+
 pub fn solve(input: &[u8], area: Cuboid) -> (u64,u64) {
-    let (_,steps) = parser::parse(input).unwrap();
-    let solution1 = smart_solve(&steps, Some(&area));
-    let solution2 = smart_solve(&steps, None);
+    let reactor = Reactor::from_input(input);
+    let solution1 = reactor.count_on_in(&area);
+    let solution2 = reactor.count_on();
     println!("{} cubes after initialization, {} after reboot", solution1, solution2);
     (solution1,solution2)
 }
 
 #[test]
 fn test22_0() {
-    let area = Cuboid {
-        xrange: Interval {l: 0, u:20},
-        yrange: Interval {l: 0, u:20},
-        zrange: Interval {l: 0, u:20}
-    };
+    let area = Cuboid::new([Interval::new(0, 20) ; 3]);
     let solution = solve(include_bytes!("../inputs/day22.0"), area);
     assert_eq!(solution, (39,39));
 }
 
+#[test]
+fn test22_queries() {
+    let reactor = Reactor::from_input(include_bytes!("../inputs/day22.0"));
+    assert!(reactor.is_on(10, 10, 10));
+    assert!(!reactor.is_on(11, 11, 11));
+    assert!(reactor.is_on(13, 13, 13));
+    assert!(!reactor.is_on(9, 9, 9));
+    let corner = Cuboid::new([Interval::new(12, 13) ; 3]);
+    assert_eq!(reactor.count_on_in(&corner), 8);
+    let point = Cuboid::new([Interval::new(10, 10) ; 3]);
+    assert_eq!(reactor.lit_cuboids_in(&point).count(), 1);
+    assert_eq!(reactor.count_on(), 39);
+}
+
 #[test]
 fn test22_1() {
-    let area = Cuboid {
-        xrange: Interval {l: -50, u: 50},
-        yrange: Interval {l: -50, u: 50},
-        zrange: Interval {l: -50, u: 50},
-    };
+    let area = Cuboid::new([Interval::new(-50, 50) ; 3]);
     let solution = solve(include_bytes!("../inputs/day22.1"), area);
     assert_eq!(solution, (590784,39769202357779));
 }
 
 #[test]
 fn test22_2() {
-    let area = Cuboid {
-        xrange: Interval {l: -50, u: 50},
-        yrange: Interval {l: -50, u: 50},
-        zrange: Interval {l: -50, u: 50},
-    };
+    let area = Cuboid::new([Interval::new(-50, 50) ; 3]);
     let solution = solve(include_bytes!("../inputs/day22.2"), area);
     assert_eq!(solution, (658691,1228699515783640));
 }
 
 #[test]
 fn test22_3() {
-    let area = Cuboid {
-        xrange: Interval {l: -50, u: 50},
-        yrange: Interval {l: -50, u: 50},
-        zrange: Interval {l: -50, u: 50},
-    };
+    let area = Cuboid::new([Interval::new(-50, 50) ; 3]);
     let solution = solve(include_bytes!("../inputs/day22.3"), area);
     assert_eq!(solution, (474140,2758514936282235));
 }
+
+#[test]
+fn test22_solvers() {
+    let inputs: [(&[u8], i64, i64); 4] = [
+        (include_bytes!("../inputs/day22.0"), 0, 20),
+        (include_bytes!("../inputs/day22.1"), -50, 50),
+        (include_bytes!("../inputs/day22.2"), -50, 50),
+        (include_bytes!("../inputs/day22.3"), -50, 50)
+    ];
+    for (input, l, u) in inputs {
+        let (_,steps) = parser::parse(input).unwrap();
+        let area = Cuboid::new([Interval::new(l, u) ; 3]);
+        let naive = naive_solve(&steps, &area);
+        assert_eq!(smart_solve(&steps, Some(&area)), naive);
+        assert_eq!(signed_solve(&steps, Some(&area)), naive);
+        assert_eq!(sweep_solve(&steps, Some(&area)).0, naive);
+        let (volume, cells) = sweep_solve(&steps, None);
+        assert_eq!(signed_solve(&steps, None), smart_solve(&steps, None));
+        assert_eq!(volume, smart_solve(&steps, None));
+        assert!(cells > 0);
+    }
+}
+
+#[test]
+fn test22_parse() {
+    let (_,step) = parser::step(b"on x=-5..5,y=0..1,z=10..10").unwrap();
+    assert!(step.state);
+    assert_eq!(step.cuboid.volume(), 22);
+    assert!(parser::step(b"on x=10..5,y=0..1,z=0..1").is_err());
+}
@@ -2,6 +2,7 @@ use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::collections::HashSet;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug,PartialEq,Eq,Hash,Clone)]
 pub enum Position {
@@ -9,20 +10,121 @@ pub enum Position {
     SideRoom(usize,usize)
 }
 
+/* Layout of a burrow: the hallway cells, the hallway cell in front of each
+   room, and the energy spent per step by each type of amphipod. Room i is the
+   destination of the amphipods of type 'A' + i. */
+#[derive(Debug,PartialEq,Eq,Hash,Clone)]
+pub struct Burrow {
+    pub hallway_length: usize,
+    pub entrances: Vec<usize>,
+    pub costs: Vec<u32>
+}
+
+impl Burrow {
+    pub fn new(hallway_length: usize, entrances: Vec<usize>) -> Burrow {
+        assert!(entrances.len() <= PackedState::MAX_TYPES,
+            "at most {} types of amphipods", PackedState::MAX_TYPES);
+        let costs = (0..entrances.len()).map(|i| 10u32.pow(i as u32)).collect();
+        Burrow { hallway_length, entrances, costs }
+    }
+
+    pub fn standard() -> Burrow {
+        Burrow::new(11, vec![2, 4, 6, 8])
+    }
+
+    pub fn rooms(&self) -> usize {
+        self.entrances.len()
+    }
+
+    pub fn amphipod(i: usize) -> char {
+        (b'A' + i as u8) as char
+    }
+
+    pub fn room_of(c: char) -> usize {
+        (c as u8 - b'A') as usize
+    }
+
+    pub fn cost(&self, c: char) -> u32 {
+        self.costs[Burrow::room_of(c)]
+    }
+
+    pub fn is_entrance(&self, a: usize) -> bool {
+        self.entrances.contains(&a)
+    }
+
+    /* Hallway cells where an amphipod may stop */
+    pub fn stops(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.hallway_length).filter(|&a| !self.is_entrance(a))
+    }
+
+    pub fn dist(&self, src: &Position, dst: &Position) -> usize {
+        match *src {
+            Position::SideRoom(i,x) =>
+                match *dst {
+                    Position::SideRoom(j,y) =>
+                        if i == j {
+                            x.abs_diff(y)
+                        }
+                        else {
+                            self.entrances[i].abs_diff(self.entrances[j]) + x + y + 2
+                        },
+                    Position::Hallway(b) => (x+1) + self.entrances[i].abs_diff(b)
+                }
+            Position::Hallway(a) =>
+                match *dst {
+                    Position::SideRoom(j,y) => (y+1) + self.entrances[j].abs_diff(a),
+                    Position::Hallway(b) => a.abs_diff(b)
+                }
+        }
+    }
+}
+
 #[derive(Debug,PartialEq,Eq,Hash,Clone)]
 pub struct State {
-    hallway: [char ; 11],
-    rooms: [Vec<char> ; 4]
+    hallway: Vec<char>,
+    rooms: Vec<Vec<char>>,
+    burrow: Rc<Burrow>
 }
 
 impl State {
     pub fn init(rooms: [Vec<char> ; 4]) -> State {
+        State::new(Rc::new(Burrow::standard()), rooms.to_vec())
+    }
+
+    pub fn new(burrow: Rc<Burrow>, rooms: Vec<Vec<char>>) -> State {
+        assert_eq!(rooms.len(), burrow.rooms());
+        let depth = rooms[0].len();
+        assert!(rooms.iter().all(|room| room.len() == depth), "rooms of different depths");
+        assert!(PackedState::fits(burrow.hallway_length, rooms.len(), depth),
+            "a burrow with {} hallway cells and {} rooms of depth {} cannot be packed",
+            burrow.hallway_length, rooms.len(), depth);
         State {
-            hallway: ['.' ; 11],
-            rooms
+            hallway: vec!['.' ; burrow.hallway_length],
+            rooms,
+            burrow
         }
     }
 
+    pub fn burrow(&self) -> &Rc<Burrow> {
+        &self.burrow
+    }
+
+    /* Inserts the two lines folded in the diagram for part 2 below the
+       first row of each room. */
+    pub fn unfold(&self) -> State {
+        const FOLDED: [[char ; 4] ; 2] = [
+            ['D','C','B','A'],
+            ['D','B','A','C']];
+        assert_eq!(self.rooms.len(), 4);
+        let mut rooms = self.rooms.clone();
+        for (i,room) in rooms.iter_mut().enumerate() {
+            room.splice(1..1, FOLDED.iter().map(|row| row[i]));
+        }
+        let mut state = State::new(self.burrow.clone(), rooms);
+        state.hallway = self.hallway.clone();
+        state
+    }
+
     pub fn get(&self, p: &Position) -> char {
         match *p {
             Position::Hallway(a) =>
@@ -45,6 +147,15 @@ impl State {
         *self.get_mut(p) = c
     }
 
+    pub fn in_bounds(&self, p: &Position) -> bool {
+        match *p {
+            Position::Hallway(a) =>
+                a < self.hallway.len(),
+            Position::SideRoom(i,x) =>
+                i < self.rooms.len() && x < self.rooms[i].len()
+        }
+    }
+
     pub fn is_free(&self, p: &Position) -> bool {
         self.get(p) == '.'
     }
@@ -74,15 +185,224 @@ impl State {
         assert_eq!(*dst_c, '.');
         *dst_c = c;
 
-        dist(src,dst) as u32 * cost(c)
+        self.burrow.dist(src,dst) as u32 * self.burrow.cost(c)
+    }
+
+    /* Checks that a move follows the rules before it is played. */
+    pub fn check_move(&self, m: &Move) -> Result<(), IllegalMove> {
+        if !self.in_bounds(&m.src) || !self.in_bounds(&m.dst) {
+            return Err(IllegalMove::OutOfBounds);
+        }
+        let c = self.get(&m.src);
+        if c == '.' {
+            return Err(IllegalMove::NoAmphipod);
+        }
+        if c != m.amphipod {
+            return Err(IllegalMove::WrongAmphipod);
+        }
+
+        // Leaving the source room, if any, then walking through the hallway
+        let (a, b) = match (&m.src, &m.dst) {
+            (Position::Hallway(_), Position::Hallway(_)) =>
+                return Err(IllegalMove::HallwayToHallway),
+            (Position::SideRoom(i,_), Position::SideRoom(j,_)) if i == j =>
+                return Err(IllegalMove::StaysInRoom),
+            (Position::Hallway(a), Position::SideRoom(j,_)) =>
+                (*a, self.burrow.entrances[*j]),
+            (Position::SideRoom(i,_), Position::Hallway(b)) =>
+                (self.burrow.entrances[*i], *b),
+            (Position::SideRoom(i,_), Position::SideRoom(j,_)) =>
+                (self.burrow.entrances[*i], self.burrow.entrances[*j])
+        };
+        if let Position::SideRoom(i,x) = m.src {
+            if !self.rooms[i][..x].iter().all(|&c| c == '.') {
+                return Err(IllegalMove::Blocked);
+            }
+        }
+        if !self.is_hallway_free(a, b) {
+            return Err(IllegalMove::Blocked);
+        }
+
+        match m.dst {
+            Position::Hallway(b) =>
+                if self.burrow.is_entrance(b) {
+                    return Err(IllegalMove::InFrontOfRoom);
+                },
+            Position::SideRoom(j,y) => {
+                let room = &self.rooms[j];
+                if c != Burrow::amphipod(j) {
+                    return Err(IllegalMove::ForeignRoom);
+                }
+                if room.iter().any(|&d| d != '.' && d != c) {
+                    return Err(IllegalMove::MixedRoom);
+                }
+                if !room[..=y].iter().all(|&c| c == '.') {
+                    return Err(IllegalMove::Blocked);
+                }
+            }
+        }
+
+        let steps = self.burrow.dist(&m.src, &m.dst);
+        if m.steps != steps || m.energy != steps as u32 * self.burrow.cost(c) {
+            return Err(IllegalMove::WrongCost);
+        }
+        Ok(())
+    }
+}
+
+mod parser {
+    use nom::{
+        IResult, character::complete::*, bytes::complete::*,
+        multi::*, sequence::*, combinator::*};
+    use super::*;
+
+    fn is_cell(c: char) -> bool {
+        c == '.' || c.is_ascii_uppercase()
+    }
+
+    fn hallway(input: &[u8]) -> IResult<&[u8], Vec<char>> {
+        delimited(
+            char('#'),
+            many1(satisfy(is_cell)),
+            pair(char('#'), line_ending))(input)
+    }
+
+    /* A row of the rooms, as the columns and contents of its cells */
+    fn row(input: &[u8]) -> IResult<&[u8], Vec<(usize,char)>> {
+        let (rest, line) = terminated(
+            take_while1(|c| c == b' ' || c == b'#' || is_cell(c as char)),
+            line_ending)(input)?;
+        let cells: Vec<(usize,char)> = line.iter()
+            .enumerate()
+            .filter(|(_,&c)| is_cell(c as char))
+            .map(|(x,&c)| (x, c as char))
+            .collect();
+        if cells.is_empty() {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input, nom::error::ErrorKind::Verify)));
+        }
+        Ok((rest, cells))
+    }
+
+    /* The rooms are found at the columns of the cells of the first row, the
+       entrance being right above them. */
+    pub fn parse(input: &[u8]) -> IResult<&[u8], State> {
+        let (input, (_, hallway, rows, _)) = tuple((
+            pair(many1(char('#')), line_ending),
+            hallway,
+            many1(row),
+            all_consuming(tuple((space0, many1(char('#')), multispace0)))
+        ))(input)?;
+
+        let columns: Vec<usize> = rows[0].iter().map(|&(x,_)| x).collect();
+        let fail = || nom::Err::Failure(nom::error::Error::new(
+            input, nom::error::ErrorKind::Verify));
+        let valid_columns = columns.iter().all(|&x| x >= 1 && x <= hallway.len());
+        let aligned = rows.iter().all(|row|
+            row.iter().map(|&(x,_)| x).eq(columns.iter().copied()));
+        if !PackedState::fits(hallway.len(), columns.len(), rows.len()) {
+            return Err(fail());
+        }
+        let max_amphipod = Burrow::amphipod(columns.len() - 1);
+        let known = |c: char| c == '.' || c <= max_amphipod;
+        let known_cells =
+            hallway.iter().all(|&c| known(c)) &&
+            rows.iter().flatten().all(|&(_,c)| known(c));
+        if !valid_columns || !aligned || !known_cells {
+            return Err(fail());
+        }
+        // As many amphipods of each type as there are places in its room
+        let cells: Vec<char> = hallway.iter().copied()
+            .chain(rows.iter().flatten().map(|&(_,c)| c))
+            .collect();
+        let complete = (0..columns.len()).all(|i|
+            cells.iter().filter(|&&c| c == Burrow::amphipod(i)).count() == rows.len());
+        if !complete {
+            return Err(fail());
+        }
+
+        let burrow = Burrow::new(hallway.len(), columns.iter().map(|x| x - 1).collect());
+        let rooms = (0..columns.len())
+            .map(|i| rows.iter().map(|row| row[i].1).collect())
+            .collect();
+        let mut state = State::new(Rc::new(burrow), rooms);
+        state.hallway = hallway;
+        Ok((input, state))
+    }
+}
+
+/* A State packed in 3 bits per cell, the hallway first and then each room
+   from top to bottom, with the depth of the rooms in the 4 highest bits. It
+   is used as the key of the search tables instead of the State itself. */
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub struct PackedState(u128);
+
+impl PackedState {
+    const CELL_BITS: usize = 3;
+    const CELL_MASK: u128 = 0b111;
+    const DEPTH_SHIFT: usize = 124;
+
+    /* Amphipod types that fit in a cell next to the empty cell */
+    pub const MAX_TYPES: usize = 7;
+
+    /* Whether the states of a burrow with these dimensions can be packed */
+    pub fn fits(hallway_length: usize, rooms: usize, depth: usize) -> bool {
+        let cell_count = hallway_length + rooms * depth;
+        rooms <= PackedState::MAX_TYPES &&
+        depth < 1 << (128 - PackedState::DEPTH_SHIFT) &&
+        cell_count * PackedState::CELL_BITS <= PackedState::DEPTH_SHIFT
+    }
+
+    fn encode(c: char) -> u128 {
+        match c {
+            '.' => 0,
+            'A'..='G' => (c as u8 - b'A') as u128 + 1,
+            _ => panic!("cannot pack {}", c)
+        }
+    }
+
+    fn decode(v: u128) -> char {
+        if v == 0 {'.'} else {(b'A' + v as u8 - 1) as char}
+    }
+}
+
+impl From<&State> for PackedState {
+    fn from(state: &State) -> Self {
+        let depth = state.rooms[0].len();
+        let cells = state.hallway.iter().chain(state.rooms.iter().flatten());
+        assert!(PackedState::fits(state.hallway.len(), state.rooms.len(), depth));
+        let mut bits = (depth as u128) << PackedState::DEPTH_SHIFT;
+        for (n,&c) in cells.enumerate() {
+            bits |= PackedState::encode(c) << (n * PackedState::CELL_BITS);
+        }
+        PackedState(bits)
+    }
+}
+
+impl PackedState {
+    pub fn unpack(self, burrow: &Rc<Burrow>) -> State {
+        let PackedState(bits) = self;
+        let depth = (bits >> PackedState::DEPTH_SHIFT) as usize;
+        let cell = |n: usize|
+            PackedState::decode((bits >> (n * PackedState::CELL_BITS)) & PackedState::CELL_MASK);
+        let length = burrow.hallway_length;
+        State {
+            hallway: (0..length).map(cell).collect(),
+            rooms: (0..burrow.rooms())
+                .map(|i| (0..depth).map(|x| cell(length + i * depth + x)).collect())
+                .collect(),
+            burrow: burrow.clone()
+        }
     }
 }
 
 #[derive(Debug,PartialEq,Eq,Hash,Clone)]
 pub struct StateAndCost {
     state: State,
-    pred: State,
-    cost: u32
+    pred: PackedState,
+    mov: Option<(Position,Position)>,
+    cost: u32,
+    estimate: u32
 }
 
 impl StateAndCost {
@@ -91,10 +411,12 @@ impl StateAndCost {
          
     }
 
-    pub fn copy_and_mov(&self, src: &Position, dst: &Position) -> Self {
+    pub fn copy_and_mov(&self, src: &Position, dst: &Position, heuristic: fn(&State) -> u32) -> Self {
         let mut other = self.clone();
         other.mov(src, dst);
-        other.pred = self.state.clone();
+        other.pred = PackedState::from(&self.state);
+        other.mov = Some((src.clone(), dst.clone()));
+        other.estimate = other.cost + heuristic(&other.state);
         other
     }
 }
@@ -102,20 +424,43 @@ impl StateAndCost {
 
 impl std::fmt::Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(f, "#############")?;
-        writeln!(f, "#{}#", String::from_iter(self.hallway))?;
-        let get = |i,j| (&self.rooms[i as usize] as &Vec<char>)[j as usize];
-        writeln!(f, "###{}#{}#{}#{}###", get(0,0), get(1,0), get(2,0), get(3,0))?;
-        for x in 1..self.rooms[0].len() {
-            writeln!(f, "  #{}#{}#{}#{}#", get(0,x), get(1,x), get(2,x), get(3,x))?;
+        let width = self.hallway.len() + 2;
+        let columns: Vec<usize> = self.burrow.entrances.iter().map(|a| a + 1).collect();
+        let first = columns[0] - 1;
+        let last = columns[columns.len() - 1] + 1;
+        writeln!(f, "{}", "#".repeat(width))?;
+        writeln!(f, "#{}#", String::from_iter(&self.hallway))?;
+        for x in 0..self.rooms[0].len() {
+            let mut line = String::new();
+            for col in 0..width {
+                if let Some(i) = columns.iter().position(|&c| c == col) {
+                    line.push(self.rooms[i][x]);
+                }
+                else if x == 0 || (first..=last).contains(&col) {
+                    line.push('#');
+                }
+                else if col < first {
+                    line.push(' ');
+                }
+            }
+            writeln!(f, "{}", line)?;
+        }
+        writeln!(f, "{}{}", " ".repeat(first), "#".repeat(last - first + 1))
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Position::Hallway(a) => write!(f, "hallway {}", a),
+            Position::SideRoom(i,x) => write!(f, "room {} depth {}", i, x)
         }
-        writeln!(f, "  #########")
     }
 }
 
 impl Ord for StateAndCost {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.cost.cmp(&self.cost)
+        other.estimate.cmp(&self.estimate)
     }
 }
 
@@ -125,81 +470,175 @@ impl PartialOrd for StateAndCost {
     }
 }
 
-fn ndist(x: usize, y: usize) -> usize {
-    if x > y {x - y} else {y - x}
+/* Sum, over the amphipods which are not in their room, of the energy needed
+   to reach the entrance of their room if nothing was in the way. It never
+   overestimates the remaining cost, and a move never decreases it by more
+   than its own cost, which is what A* needs to stay optimal. */
+pub fn lower_bound(state: &State) -> u32 {
+    let burrow = &state.burrow;
+    let mut bound = 0;
+    for (a,&c) in state.hallway.iter().enumerate() {
+        if c != '.' {
+            let j = Burrow::room_of(c);
+            let steps = burrow.dist(&Position::Hallway(a), &Position::SideRoom(j, 0));
+            bound += steps as u32 * burrow.cost(c);
+        }
+    }
+    for (i,room) in state.rooms.iter().enumerate() {
+        for (x,&c) in room.iter().enumerate() {
+            if c != '.' {
+                let j = Burrow::room_of(c);
+                if i != j {
+                    let steps = burrow.dist(&Position::SideRoom(i, x), &Position::SideRoom(j, 0));
+                    bound += steps as u32 * burrow.cost(c);
+                }
+            }
+        }
+    }
+    bound
 }
 
-fn cost(c: char) -> u32 {
-    match c {
-        'A' => 1,
-        'B' => 10,
-        'C' => 100,
-        'D' => 1000,
-        _ => panic!()
+fn no_heuristic(_state: &State) -> u32 {
+    0
+}
+
+#[derive(Debug,Clone,Copy)]
+pub enum Strategy {
+    Dijkstra,
+    AStar
+}
+
+#[derive(Debug,PartialEq,Eq,Clone)]
+pub struct Move {
+    pub amphipod: char,
+    pub src: Position,
+    pub dst: Position,
+    pub steps: usize,
+    pub energy: u32
+}
+
+impl Move {
+    pub fn new(burrow: &Burrow, amphipod: char, src: Position, dst: Position) -> Move {
+        let steps = burrow.dist(&src, &dst);
+        Move { amphipod, src, dst, steps, energy: steps as u32 * burrow.cost(amphipod) }
     }
 }
 
-fn dist(src: &Position, dst: &Position) -> usize {
-    match *src {
-        Position::SideRoom(i,x) =>
-            match *dst {
-                Position::SideRoom(j,y) =>
-                    if i == j {
-                        ndist(x,y)
-                    }
-                    else {
-                        ndist(i,j) * 2 + x + y + 2
-                    },
-                Position::Hallway(b) => (x+1) + ndist(2*i+2,b)
-            }
-        Position::Hallway(a) =>
-            match *dst {
-                Position::SideRoom(j,y) => (y+1) + ndist(2*j+2,a),
-                Position::Hallway(b) => ndist(a,b)
-            }
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} from {} to {}: {} steps, {} energy",
+            self.amphipod, self.src, self.dst, self.steps, self.energy)
     }
 }
 
-fn count() {
-    static mut COUNT: u64 = 0;
-    unsafe {
-        COUNT += 1;
-        if COUNT % 1000 == 0 { 
-            println!("Explored {} states", COUNT);
+#[derive(Debug,PartialEq,Eq)]
+pub enum IllegalMove {
+    OutOfBounds,
+    NoAmphipod,
+    WrongAmphipod,
+    HallwayToHallway,
+    StaysInRoom,
+    InFrontOfRoom,
+    ForeignRoom,
+    MixedRoom,
+    Blocked,
+    WrongCost
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Default)]
+pub struct Plan {
+    pub moves: Vec<Move>
+}
+
+impl Plan {
+    pub fn energy(&self) -> u32 {
+        self.moves.iter().map(|m| m.energy).sum()
+    }
+
+    /* Replays the plan from the initial state and returns the final state,
+       or the index of the first illegal move. */
+    pub fn validate(&self, initial: &State) -> Result<State, (usize,IllegalMove)> {
+        let mut state = initial.clone();
+        for (n,m) in self.moves.iter().enumerate() {
+            state.check_move(m).map_err(|e| (n, e))?;
+            state.mov(&m.src, &m.dst);
         }
+        Ok(state)
     }
 }
 
-fn show_preds(preds: &HashMap<State, (State,u32)>, state: &State) {
-    if let Some((pred,cost)) = preds.get(state) {
-        if pred != state {
-            show_preds(preds, pred);
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for m in &self.moves {
+            writeln!(f, "{}", m)?;
         }
-        println!("{}\ncost:{}\n", state, cost);
+        writeln!(f, "total energy: {}", self.energy())
+    }
+}
+
+#[derive(Debug,PartialEq,Eq)]
+pub struct SearchResult {
+    pub cost: u32,
+    pub plan: Plan,
+    pub expanded: usize
+}
+
+type Preds = HashMap<PackedState, (PackedState,Option<(Position,Position)>)>;
+
+fn build_plan(burrow: &Rc<Burrow>, preds: &Preds, mut state: PackedState) -> Plan {
+    let mut moves = Vec::new();
+    while let Some((pred, Some((src,dst)))) = preds.get(&state) {
+        let amphipod = pred.unpack(burrow).get(src);
+        moves.push(Move::new(burrow, amphipod, src.clone(), dst.clone()));
+        state = *pred;
     }
+    moves.reverse();
+    Plan { moves }
 }
 
-pub fn solve(input: [Vec<char> ; 4]) -> u32 {
-    let initial = State::init(input);
+pub fn solve(input: [Vec<char> ; 4]) -> Plan {
+    search(State::init(input), Strategy::Dijkstra).plan
+}
+
+pub fn solve_with(input: [Vec<char> ; 4], strategy: Strategy) -> SearchResult {
+    search(State::init(input), strategy)
+}
+
+pub fn solve_input(input: &[u8]) -> (u32,u32) {
+    let (_,initial) = parser::parse(input).unwrap();
+    let solution1 = search(initial.clone(), Strategy::Dijkstra).cost;
+    let solution2 = search(initial.unfold(), Strategy::Dijkstra).cost;
+    println!("least energy: {}, with the unfolded diagram: {}", solution1, solution2);
+    (solution1, solution2)
+}
+
+pub fn search(initial: State, strategy: Strategy) -> SearchResult {
+    let heuristic = match strategy {
+        Strategy::Dijkstra => no_heuristic,
+        Strategy::AStar => lower_bound
+    };
     let mut states = HashSet::new();
     let mut pending: BinaryHeap<StateAndCost> = BinaryHeap::new();
-    let mut preds = HashMap::new();
-    pending.push(StateAndCost{state: initial.clone(), pred: initial, cost:0});
+    let mut preds: Preds = HashMap::new();
+    let estimate = heuristic(&initial);
+    let pred = PackedState::from(&initial);
+    pending.push(StateAndCost{state: initial, pred, mov: None, cost:0, estimate});
 
     while let Some(state_and_cost) = pending.pop() {
-        if states.get(&state_and_cost.state).is_some() {
+        let packed = PackedState::from(&state_and_cost.state);
+        if !states.insert(packed) {
             continue;
         }
-        states.insert(state_and_cost.state.clone());
-        preds.insert(state_and_cost.state.clone(), (state_and_cost.pred.clone(), state_and_cost.cost));
+        preds.insert(packed, (state_and_cost.pred, state_and_cost.mov.clone()));
         let state = &state_and_cost.state;
 
         // --- Are there free rooms ? ---
 
-        let mut free_rooms: [usize ; 4] = [0 ; 4];
+        let burrow = &state.burrow;
+        let mut free_rooms = vec![0 ; burrow.rooms()];
         let mut finished = true;
         for (i,room) in state.rooms.iter().enumerate() {
-            let c = (b'A' + i as u8) as char;
+            let c = Burrow::amphipod(i);
             for x in (0..room.len()).rev() {
                 if room[x] == '.' {
                     free_rooms[i] = x + 1;
@@ -214,9 +653,11 @@ pub fn solve(input: [Vec<char> ; 4]) -> u32 {
         }
 
         if finished {
-            println!("Found a solution !");
-            show_preds(&preds, state);
-            return state_and_cost.cost;
+            return SearchResult {
+                cost: state_and_cost.cost,
+                plan: build_plan(burrow, &preds, packed),
+                expanded: states.len()
+            };
         }
 
         // --- First, try to empty the hallway ---
@@ -227,12 +668,12 @@ pub fn solve(input: [Vec<char> ; 4]) -> u32 {
             match c {
                 '.' => (),
                 _ => {
-                    let i = (c as u8 - b'A') as usize;
-                    let b = 2 * i + 2;
+                    let i = Burrow::room_of(c);
+                    let b = burrow.entrances[i];
                     if free_rooms[i] > 0 && state.is_hallway_free(a, b) {
                         let src = Position::Hallway(a);
                         let dst = Position::SideRoom(i, free_rooms[i]-1);
-                        let new_state = state_and_cost.copy_and_mov(&src, &dst);
+                        let new_state = state_and_cost.copy_and_mov(&src, &dst, heuristic);
                         pending.push(new_state);
                         priority_changes = true;
                         break;
@@ -247,42 +688,38 @@ pub fn solve(input: [Vec<char> ; 4]) -> u32 {
 
         // --- Then, leave rooms ---
 
-        for i in 0..=3 {
-            let room = &state.rooms[i];
-           
+        for (i,room) in state.rooms.iter().enumerate() {
             let x =
                 if let Some(x) = room.iter().position(|&c| c != '.') {
                     x
                 } else {
                     continue;
                 };
-            let a = 2 * i + 2;
+            let a = burrow.entrances[i];
             let src = Position::SideRoom(i,x);
             let c = room[x];
 
             // Is it possible to go to dest room ?
-            let j = (c as u8 - b'A') as usize;
-            let b = 2 * j + 2;
+            let j = Burrow::room_of(c);
+            let b = burrow.entrances[j];
 
             if i != j && free_rooms[j] > 0 && state.is_hallway_free(a, b) {
                 let dst = Position::SideRoom(j, free_rooms[j]-1);
-                let new_state = state_and_cost.copy_and_mov(&src, &dst);
+                let new_state = state_and_cost.copy_and_mov(&src, &dst, heuristic);
                 pending.push(new_state);
             }
 
             // Otherwise, go to one of the free spot of the hallway
             if i != j || free_rooms[j] == 0 { // Do not leave if already in place
-                for b in [0,1,3,5,7,9,10] {
+                for b in burrow.stops() {
                     if state.is_hallway_free(a, b) {
                         let dst = Position::Hallway(b);
-                        let new_state = state_and_cost.copy_and_mov(&src, &dst);
+                        let new_state = state_and_cost.copy_and_mov(&src, &dst, heuristic);
                         pending.push(new_state);
                     }
                 }
             }
         }
-
-        count();
     }
 
     panic!("No solution found")
@@ -296,7 +733,7 @@ fn test23_0() {
         vec!['C','D'],
         vec!['B','C'],
         vec!['D','A']]);
-    assert_eq!(solution, 12521);
+    assert_eq!(solution.energy(), 12521);
 }
 
 #[test]
@@ -306,7 +743,7 @@ fn test23_1() {
         vec!['A','A'],
         vec!['D','B'],
         vec!['C','B']]);
-    assert_eq!(solution, 14546);
+    assert_eq!(solution.energy(), 14546);
 }
 
 #[test]
@@ -316,7 +753,7 @@ fn test23_2() {
         vec!['C','C','B','D'],
         vec!['B','B','A','C'],
         vec!['D','A','C','A']]);
-    assert_eq!(solution, 44169);
+    assert_eq!(solution.energy(), 44169);
 }
 
 #[test]
@@ -326,6 +763,215 @@ fn test23_3() {
         vec!['A','C','B','A'],
         vec!['D','B','A','B'],
         vec!['C','A','C','B']]);
-    assert_eq!(solution, 42308);
+    assert_eq!(solution.energy(), 42308);
+}
+
+
+#[test]
+fn test23_parse() {
+    let input = include_bytes!("../inputs/day23.0");
+    let (_,state) = parser::parse(input).unwrap();
+    assert_eq!(state, State::init([
+        vec!['B','A'],
+        vec!['C','D'],
+        vec!['B','C'],
+        vec!['D','A']]));
+    assert_eq!(format!("{}", state).as_bytes(), input);
+
+    let mut unfolded = state.unfold();
+    unfolded.hallway[3] = 'B';
+    unfolded.rooms[0][0] = '.';
+    let printed = format!("{}", unfolded);
+    let (_,parsed) = parser::parse(printed.as_bytes()).unwrap();
+    assert_eq!(parsed, unfolded);
+    assert_eq!(parsed.rooms[0].len(), 4);
+
+    let short = String::from_utf8_lossy(input).replace("#A#D#C#A#", "#A#D#C#");
+    assert!(parser::parse(short.as_bytes()).is_err());
+}
+
+#[test]
+fn test23_input_0() {
+    let solution = solve_input(include_bytes!("../inputs/day23.0"));
+    assert_eq!(solution, (12521,44169));
+}
+
+#[test]
+fn test23_input_1() {
+    let solution = solve_input(include_bytes!("../inputs/day23.1"));
+    assert_eq!(solution, (14546,42308));
 }
 
+#[test]
+fn test23_astar() {
+    let inputs = [
+        [vec!['B','A'], vec!['C','D'], vec!['B','C'], vec!['D','A']],
+        [vec!['D','C'], vec!['A','A'], vec!['D','B'], vec!['C','B']],
+        [vec!['B','D','D','A'], vec!['C','C','B','D'], vec!['B','B','A','C'], vec!['D','A','C','A']],
+        [vec!['D','D','D','C'], vec!['A','C','B','A'], vec!['D','B','A','B'], vec!['C','A','C','B']]
+    ];
+    for input in inputs {
+        let dijkstra = solve_with(input.clone(), Strategy::Dijkstra);
+        let astar = solve_with(input, Strategy::AStar);
+        assert_eq!(astar.cost, dijkstra.cost);
+        assert!(astar.expanded <= dijkstra.expanded);
+    }
+}
+
+#[test]
+fn test23_packed() {
+    let (_,state) = parser::parse(include_bytes!("../inputs/day23.1")).unwrap();
+    let mut state = state.unfold();
+    let burrow = state.burrow().clone();
+    assert_eq!(PackedState::from(&state).unpack(&burrow), state);
+    let packed = PackedState::from(&state);
+    state.mov(&Position::SideRoom(3, 0), &Position::Hallway(10));
+    state.mov(&Position::SideRoom(0, 0), &Position::Hallway(0));
+    assert_eq!(PackedState::from(&state).unpack(&burrow), state);
+    assert_ne!(PackedState::from(&state), packed);
+    let shallow = State::init([vec!['.'], vec!['.'], vec!['.'], vec!['.']]);
+    assert_eq!(PackedState::from(&shallow).unpack(&burrow), shallow);
+}
+
+#[test]
+fn test23_plan() {
+    let (_,initial) = parser::parse(include_bytes!("../inputs/day23.0")).unwrap();
+    for initial in [initial.clone(), initial.unfold()] {
+        let result = search(initial.clone(), Strategy::AStar);
+        assert_eq!(result.plan.energy(), result.cost);
+        let depth = initial.rooms[0].len();
+        let finished = State::init(core::array::from_fn(|i|
+            vec![Burrow::amphipod(i) ; depth]));
+        assert_eq!(result.plan.validate(&initial), Ok(finished));
+    }
+}
+
+#[test]
+fn test23_illegal_moves() {
+    let (_,initial) = parser::parse(include_bytes!("../inputs/day23.0")).unwrap();
+    let check = |moves: Vec<Move>| Plan { moves }.validate(&initial).err();
+    let burrow = Burrow::standard();
+    let mv = |c, src, dst| Move::new(&burrow, c, src, dst);
+    use Position::*;
+    assert_eq!(check(vec![mv('B', SideRoom(0,0), Hallway(4))]),
+        Some((0, IllegalMove::InFrontOfRoom)));
+    assert_eq!(check(vec![mv('A', SideRoom(0,1), Hallway(0))]),
+        Some((0, IllegalMove::Blocked)));
+    assert_eq!(check(vec![
+            mv('B', SideRoom(2,0), Hallway(3)),
+            mv('B', SideRoom(0,0), Hallway(5))]),
+        Some((1, IllegalMove::Blocked)));
+    assert_eq!(check(vec![
+            mv('C', SideRoom(1,0), Hallway(3)),
+            mv('B', SideRoom(2,0), Hallway(5)),
+            mv('C', Hallway(3), SideRoom(2,1))]),
+        Some((2, IllegalMove::Blocked)));
+    assert_eq!(check(vec![
+            mv('B', SideRoom(0,0), Hallway(3)),
+            mv('B', Hallway(3), SideRoom(2,0))]),
+        Some((1, IllegalMove::ForeignRoom)));
+    assert_eq!(check(vec![
+            mv('B', SideRoom(0,0), Hallway(3)),
+            mv('B', Hallway(3), Hallway(5))]),
+        Some((1, IllegalMove::HallwayToHallway)));
+    assert_eq!(check(vec![mv('C', SideRoom(1,0), SideRoom(2,0))]),
+        Some((0, IllegalMove::MixedRoom)));
+    assert_eq!(check(vec![Move { energy: 1, ..mv('B', SideRoom(0,0), Hallway(3)) }]),
+        Some((0, IllegalMove::WrongCost)));
+    let out = mv('B', SideRoom(0,0), Hallway(3));
+    assert_eq!(check(vec![Move { dst: Hallway(42), ..out.clone() }]),
+        Some((0, IllegalMove::OutOfBounds)));
+    assert_eq!(check(vec![Move { src: SideRoom(7,0), ..out.clone() }]),
+        Some((0, IllegalMove::OutOfBounds)));
+    assert_eq!(check(vec![out.clone(), Move { src: Hallway(3), dst: SideRoom(1,2), ..out }]),
+        Some((1, IllegalMove::OutOfBounds)));
+}
+
+#[test]
+fn test23_burrow() {
+    let (_,initial) = parser::parse(b"\
+#########
+#.......#
+###B#A###
+  #A#B#
+  #####
+").unwrap();
+    assert_eq!(initial.burrow().entrances, vec![2, 4]);
+    assert_eq!(search(initial, Strategy::AStar).cost, 46);
+
+    let diagrams: [&[u8] ; 2] = [b"\
+###############
+#.............#
+###B#A#D#E#C###
+  #A#B#C#D#E#
+  ###########
+", b"\
+#################
+#...............#
+####B#C###B#D####
+   #A#D###C#A#
+   ###########
+"];
+    for diagram in diagrams {
+        let (_,initial) = parser::parse(diagram).unwrap();
+        assert_eq!(format!("{}", initial).as_bytes(), diagram);
+        let dijkstra = search(initial.clone(), Strategy::Dijkstra);
+        let astar = search(initial.clone(), Strategy::AStar);
+        assert_eq!(astar.cost, dijkstra.cost);
+        let finished = astar.plan.validate(&initial).unwrap();
+        assert!(finished.rooms.iter().enumerate().all(|(i,room)|
+            room.iter().all(|&c| c == Burrow::amphipod(i))));
+    }
+
+    // Eight types of amphipods, or too many cells, cannot be packed
+    let unpackable: [&[u8] ; 2] = [b"\
+#####################
+#...................#
+###B#A#D#E#C#H#F#G###
+  #A#B#C#D#E#F#G#H#
+  #################
+", b"\
+#########
+#.......#
+###B#A###
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #A#B#
+  #####
+"];
+    for diagram in unpackable {
+        assert!(parser::parse(diagram).is_err());
+    }
+
+    // Three B and a single A, or an A waiting in the hallway for a third
+    let unsolvable: [&[u8] ; 2] = [b"\
+#########
+#.......#
+###B#A###
+  #B#B#
+  #####
+", b"\
+#########
+#A......#
+###B#A###
+  #A#B#
+  #####
+"];
+    for diagram in unsolvable {
+        assert!(parser::parse(diagram).is_err());
+    }
+}
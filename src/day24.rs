@@ -3,6 +3,9 @@
 #![allow(clippy::should_implement_trait)]
 
 use std::collections::HashSet;
+use std::hash::{Hash,Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize,Ordering};
 
 #[derive(Debug,PartialEq,Eq,Clone,Copy)]
 pub enum Register { W, X, Y, Z }
@@ -496,6 +499,15 @@ struct CachedState {
     instruction: usize
 }
 
+fn digits(ascending: bool) -> Vec<i64> {
+    if ascending {
+        (1..=9).collect()
+    }
+    else {
+        (1..=9).rev().collect()
+    }
+}
+
 fn branched_execution(
         cache: &mut HashSet<CachedState>,
         program: &[(usize,Instruction)],
@@ -510,14 +522,7 @@ fn branched_execution(
         cache.insert(cs);
 
         if let Instruction::Inp(_) = instruction {
-            let range: Vec<i64> =
-                if ascending {
-                    (1..=9).collect()
-                }
-                else {
-                    (1..=9).rev().collect()
-                };
-            for x in range  {               
+            for x in digits(ascending) {
                 let mut state = state.clone();
                 state.execute(instruction, Some(x));
                 let final_state = forward_interpret(&program[(i+1)..], &state);
@@ -544,7 +549,7 @@ fn branched_execution(
 
 pub fn execute(program: &[Instruction], input: &[i64]) {
     let mut state = State::initial();
-    let mut iter = input.to_owned().into_iter();
+    let mut iter = input.iter().copied();
 
     for (i,instruction) in program.iter().enumerate() {
         println!("{}", state);
@@ -566,6 +571,193 @@ fn solve_part(program: &[(usize,Instruction)], part2: bool) -> i64 {
     result.iter().rev().fold(0, |acc, d| acc * 10 + d)
 }
 
+/* Set of states from which no input sequence can bring z back to 0, shared
+   between the worker threads. It is split into shards so that threads
+   rarely wait on the same lock. */
+struct DeadStates {
+    shards: Vec<Mutex<HashSet<CachedState>>>
+}
+
+impl DeadStates {
+    fn new(shard_count: usize) -> Self {
+        Self {
+            shards: (0..shard_count).map(|_| Mutex::new(HashSet::new())).collect()
+        }
+    }
+
+    fn shard(&self, cs: &CachedState) -> &Mutex<HashSet<CachedState>> {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        cs.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    fn contains(&self, cs: &CachedState) -> bool {
+        self.shard(cs).lock().unwrap().contains(cs)
+    }
+
+    fn insert(&self, cs: CachedState) {
+        self.shard(&cs).lock().unwrap().insert(cs);
+    }
+}
+
+/* Same exploration as branched_execution, except that states are only
+   recorded once they are known to be dead ends: a state being explored by
+   another thread may still lead to a solution. The search gives up, without
+   recording anything, as soon as abort returns true. */
+fn concurrent_branched_execution(
+        dead: &DeadStates,
+        abort: &dyn Fn() -> bool,
+        program: &[(usize,Instruction)],
+        mut state: State,
+        ascending: bool) -> Option<Vec<i64>>
+{
+    let mut visited = Vec::new();
+
+    for (i,(n,instruction)) in program.iter().enumerate() {
+        let cs = CachedState {state: state.clone(), instruction: *n};
+        if dead.contains(&cs) {
+            break;
+        }
+        visited.push(cs);
+
+        if let Instruction::Inp(_) = instruction {
+            for x in digits(ascending) {
+                if abort() {
+                    return None;
+                }
+                let mut state = state.clone();
+                state.execute(instruction, Some(x));
+                let final_state = forward_interpret(&program[(i+1)..], &state);
+                let interval = final_state.get(&Register::Z);
+                if let Interval {l:0, u:0} = interval {
+                    return Some(vec![x]);
+                } 
+                else if interval.contains(0) {
+                    if let Some(mut v) = concurrent_branched_execution(
+                            dead, abort, &program[(i+1)..], state, ascending) {
+                        v.push(x);
+                        return Some(v);
+                    }
+                }
+            }
+            break;
+        }
+        else {
+            state.execute(instruction,None);
+        }
+    }
+
+    if abort() {
+        return None;
+    }
+    for cs in visited {
+        dead.insert(cs);
+    }
+    None
+}
+
+/* A search task: the first digits of the serial number have been fixed and
+   the program executed up to the next input instruction. */
+struct Task<'a> {
+    prefix: Vec<i64>,
+    state: State,
+    program: &'a [(usize,Instruction)],
+    complete: bool
+}
+
+/* Enumerates, in search order, the prefixes of depth digits which are not
+   ruled out by the forward interpretation. */
+fn split_tasks<'a>(
+        program: &'a [(usize,Instruction)],
+        mut state: State,
+        prefix: Vec<i64>,
+        depth: usize,
+        ascending: bool,
+        tasks: &mut Vec<Task<'a>>)
+{
+    if depth == 0 {
+        tasks.push(Task { prefix, state, program, complete: false });
+        return;
+    }
+
+    for (i,(_,instruction)) in program.iter().enumerate() {
+        if let Instruction::Inp(_) = instruction {
+            for x in digits(ascending) {
+                let mut state = state.clone();
+                state.execute(instruction, Some(x));
+                let rest = &program[(i+1)..];
+                let final_state = forward_interpret(rest, &state);
+                let interval = final_state.get(&Register::Z);
+                let mut prefix = prefix.clone();
+                prefix.push(x);
+                if let Interval {l:0, u:0} = interval {
+                    tasks.push(Task { prefix, state, program: rest, complete: true });
+                }
+                else if interval.contains(0) {
+                    split_tasks(rest, state, prefix, depth - 1, ascending, tasks);
+                }
+            }
+            return;
+        }
+        else {
+            state.execute(instruction,None);
+        }
+    }
+
+    panic!("Program reads fewer than {} more digits", depth);
+}
+
+/* Splits the first split_depth digits into independent tasks, explored by
+   threads workers sharing the same table of dead states. Tasks are ordered
+   from the most to the least preferred prefix, and the answer is the
+   solution of the first task which has one, so that the result is the same
+   as the one of the sequential search. */
+fn solve_part_parallel(
+        program: &[(usize,Instruction)],
+        part2: bool,
+        split_depth: usize,
+        threads: usize) -> i64
+{
+    assert!(threads >= 1, "At least one worker thread is needed");
+    let mut tasks = Vec::new();
+    split_tasks(program, State::initial(), Vec::new(), split_depth, part2, &mut tasks);
+
+    let dead = DeadStates::new(64 * threads);
+    let next = AtomicUsize::new(0);
+    let best = AtomicUsize::new(usize::MAX);
+    let solutions = Mutex::new(vec![None ; tasks.len()]);
+
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let t = next.fetch_add(1, Ordering::SeqCst);
+                if t >= tasks.len() || t > best.load(Ordering::SeqCst) {
+                    break;
+                }
+                let task = &tasks[t];
+                let abort = || best.load(Ordering::Relaxed) < t;
+                let solution =
+                    if task.complete {
+                        Some(vec![])
+                    }
+                    else {
+                        concurrent_branched_execution(
+                            &dead, &abort, task.program, task.state.clone(), part2)
+                    };
+                if let Some(mut v) = solution {
+                    v.extend(task.prefix.iter().rev());
+                    solutions.lock().unwrap()[t] = Some(v);
+                    best.fetch_min(t, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+
+    let t = best.into_inner();
+    let result = solutions.into_inner().unwrap().swap_remove(t).unwrap();
+    result.iter().rev().fold(0, |acc, d| acc * 10 + d)
+}
+
 pub fn solve(input: &[u8]) -> (i64,i64) {
     let (_,program) = parser::parse(input).unwrap();
     let numbered_program : Vec<(usize,Instruction)> =
@@ -577,6 +769,19 @@ pub fn solve(input: &[u8]) -> (i64,i64) {
     (solution1, solution2)
 }
 
+const SPLIT_DEPTH: usize = 2;
+
+pub fn parallel_solve(input: &[u8], threads: usize) -> (i64,i64) {
+    let (_,program) = parser::parse(input).unwrap();
+    let numbered_program : Vec<(usize,Instruction)> =
+        program.into_iter().enumerate().collect();
+    let solution1 = solve_part_parallel(&numbered_program, false, SPLIT_DEPTH, threads);
+    println!("largest serial number: {}", solution1);
+    let solution2 = solve_part_parallel(&numbered_program, true, SPLIT_DEPTH, threads);
+    println!("smallest serial number: {}", solution2);
+    (solution1, solution2)
+}
+
 pub fn naive_solve(input: &[u8]) -> (i32,i32) {
     let (_,program) = parser::parse(input).unwrap();
     let mut set = HashSet::new();
@@ -610,3 +815,27 @@ fn test24() {
     let solution = solve(include_bytes!("../inputs/day24"));
     assert_eq!(solution, (96918996924991,91811241911641));
 }
+
+#[test]
+fn test24_parallel() {
+    use std::time::Instant;
+    let input = include_bytes!("../inputs/day24");
+    // A fixed number of workers, so that runs on different machines compare
+    // the same search; the speedup can only exceed 1 with as many cores.
+    let threads = 4;
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let start = Instant::now();
+    let sequential = solve(input);
+    let sequential_time = start.elapsed();
+
+    let start = Instant::now();
+    let parallel = parallel_solve(input, threads);
+    let parallel_time = start.elapsed();
+
+    println!("sequential: {:?}, parallel ({} threads on {} cores): {:?}, speedup: {:.2}",
+        sequential_time, threads, cores, parallel_time,
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64());
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel, (96918996924991,91811241911641));
+}
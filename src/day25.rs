@@ -1,10 +1,21 @@
 use array2d::Array2D;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Cell { Horizontal, Vertical, Empty }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Map(Array2D<Cell>);
 
+impl std::hash::Hash for Map {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.num_rows().hash(state);
+        self.0.num_columns().hash(state);
+        for cell in self.0.elements_row_major_iter() {
+            cell.hash(state);
+        }
+    }
+}
+
 impl std::fmt::Display for Cell {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -51,10 +62,10 @@ mod parser {
     }
 }
 
-fn hstep(map: &Map) -> (Map,bool) {
+fn hstep(map: &Map) -> (Map,usize) {
     let (n_rows,n_cols) = (map.0.num_rows(), map.0.num_columns());
     let mut result = Array2D::filled_with(Cell::Empty, n_rows, n_cols);
-    let mut movement = false;
+    let mut movement = 0;
 
     for i in 0..n_rows {
         for j in 0..n_cols {
@@ -63,7 +74,7 @@ fn hstep(map: &Map) -> (Map,bool) {
                     let next = (j + 1) % n_cols;
                     if let Some(Cell::Empty) = map.0.get(i, next) {
                         result.set(i, next, Cell::Horizontal).unwrap();
-                        movement = true;
+                        movement += 1;
                     }
                     else {
                         result.set(i, j, Cell::Horizontal).unwrap();
@@ -81,10 +92,10 @@ fn hstep(map: &Map) -> (Map,bool) {
 }
 
 
-fn vstep(map: &Map) -> (Map,bool) {
+fn vstep(map: &Map) -> (Map,usize) {
     let (n_rows,n_cols) = (map.0.num_rows(), map.0.num_columns());
     let mut result = Array2D::filled_with(Cell::Empty, n_rows, n_cols);
-    let mut movement = false;
+    let mut movement = 0;
 
     for i in 0..n_rows {
         for j in 0..n_cols {
@@ -93,7 +104,7 @@ fn vstep(map: &Map) -> (Map,bool) {
                     let next = (i + 1) % n_rows;
                     if let Some(Cell::Empty) = map.0.get(next, j) {
                         result.set(next, j, Cell::Vertical).unwrap();
-                        movement = true;
+                        movement += 1;
                     }
                     else {
                         result.set(i, j, Cell::Vertical).unwrap();
@@ -111,35 +122,193 @@ fn vstep(map: &Map) -> (Map,bool) {
 }
 
 
-pub fn solve(input: &[u8]) -> (i32,i32) {
-    let (_,mut map) = parser::parse(input).unwrap();
-    
-    println!("Initial map\n{}", map);
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    // First step at which no sea cucumber moves
+    Stable(usize),
+    // The state after start steps comes back every period steps, without
+    // ever settling
+    Cycle { start: usize, period: usize },
+    // Still moving after the step limit, without having repeated
+    Limit
+}
+
+#[derive(Debug, Clone)]
+pub struct SeaFloor {
+    map: Map,
+    steps: usize
+}
+
+impl SeaFloor {
+    pub fn new(map: Map) -> Self {
+        SeaFloor { map, steps: 0 }
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
 
-    let mut step = 0;
-    let mut movement = true;
-    while movement {
-        step += 1;
-        let (rmap,rmovement) = hstep(&map);
-        map = rmap;
-        movement = rmovement;
-        let (rmap,rmovement) = vstep(&map);
-        map = rmap;
-        movement = movement || rmovement;
-        println!("After {} steps\n{}", step, map);
+    /* Moves the east-facing herd then the south-facing one, returning the
+       number of sea cucumbers which moved. */
+    pub fn step(&mut self) -> usize {
+        let (map, hmoved) = hstep(&self.map);
+        let (map, vmoved) = vstep(&map);
+        self.map = map;
+        self.steps += 1;
+        hmoved + vmoved
     }
 
-    (step, 0)
+    /* Steps until nothing moves or a state repeats, or the total number of
+       steps reaches limit. Repetitions are found with Brent's algorithm: the
+       map is compared with a copy saved at each power of two steps, which
+       finds the period, then two floors that far apart are replayed from the
+       start to find where the cycle begins. Only three maps are kept, and the
+       floor is left on the first repeated state. */
+    pub fn run_until_stable(&mut self, limit: usize) -> Outcome {
+        let initial = self.clone();
+        let mut saved = self.map.clone();
+        let mut power = 1;
+        let mut period = 0;
+        loop {
+            if self.steps >= limit {
+                return Outcome::Limit;
+            }
+            if self.step() == 0 {
+                return Outcome::Stable(self.steps);
+            }
+            period += 1;
+            if self.map == saved {
+                break;
+            }
+            if period == power {
+                saved = self.map.clone();
+                power *= 2;
+                period = 0;
+            }
+        }
+
+        let mut first = initial.clone();
+        let mut second = initial;
+        for _ in 0..period {
+            second.step();
+        }
+        while first.map != second.map {
+            first.step();
+            second.step();
+        }
+        *self = second;
+        Outcome::Cycle { start: first.steps, period }
+    }
+
+    /* The current map and the ones after each further step, up to the stable
+       one. Endless if the sea floor never settles. */
+    pub fn states(&self) -> States {
+        States { floor: self.clone(), done: false }
+    }
+}
+
+pub struct States {
+    floor: SeaFloor,
+    done: bool
+}
+
+impl Iterator for States {
+    type Item = Map;
+
+    fn next(&mut self) -> Option<Map> {
+        if self.done {
+            return None;
+        }
+        let map = self.floor.map.clone();
+        if self.floor.step() == 0 {
+            self.done = true;
+        }
+        Some(map)
+    }
+}
+
+pub fn solve(input: &[u8]) -> usize {
+    let (_,map) = parser::parse(input).unwrap();
+    match SeaFloor::new(map).run_until_stable(usize::MAX) {
+        Outcome::Stable(step) => step,
+        outcome => panic!("Sea cucumbers never stop: {:?}", outcome)
+    }
 }
 
 #[test]
 fn test25_0() {
     let solution = solve(include_bytes!("../inputs/day25.0"));
-    assert_eq!(solution, (58,0));
+    assert_eq!(solution, 58);
 }
 
 #[test]
 fn test25_1() {
     let solution = solve(include_bytes!("../inputs/day25.1"));
-    assert_eq!(solution, (432,0));
+    assert_eq!(solution, 432);
+}
+
+#[cfg(test)]
+fn sea_floor(rows: &[&str]) -> SeaFloor {
+    let (_,map) = parser::parse(rows.join("\n").as_bytes()).unwrap();
+    SeaFloor::new(map)
+}
+
+#[cfg(test)]
+fn picture(rows: &[&str]) -> String {
+    rows.iter().map(|row| format!("{}\n", row)).collect::<String>() + "\n"
+}
+
+#[test]
+fn test25_steps() {
+    let mut floor = sea_floor(&["...>>>>>..."]);
+    assert_eq!(floor.step(), 1);
+    assert_eq!(floor.map().to_string(), picture(&["...>>>>.>.."]));
+    assert_eq!(floor.step(), 2);
+    assert_eq!(floor.map().to_string(), picture(&["...>>>.>.>."]));
+
+    let mut floor = sea_floor(&["..........", ".>v....v..", ".......>..", ".........."]);
+    assert_eq!(floor.step(), 3);
+    assert_eq!(floor.map().to_string(),
+        picture(&["..........", ".>........", "..v....v>.", ".........."]));
+
+    let floor = sea_floor(&["...>...", ".......", "......>", "v.....>", "......>", ".......", "..vvv.."]);
+    let states: Vec<String> = floor.states().take(2).map(|map| map.to_string()).collect();
+    assert_eq!(states[1],
+        picture(&["..vv>..", ".......", ">......", "v.....>", ">......", ".......", "....v.."]));
+    assert_eq!(floor.steps(), 0);
+
+    let (_,map) = parser::parse(include_bytes!("../inputs/day25.0")).unwrap();
+    let floor = SeaFloor::new(map);
+    assert_eq!(floor.states().count(), 58);
+    let last = floor.states().last().unwrap();
+    let mut floor = floor;
+    assert_eq!(floor.run_until_stable(10), Outcome::Limit);
+    assert_eq!(floor.steps(), 10);
+    assert_eq!(floor.run_until_stable(100), Outcome::Stable(58));
+    assert_eq!(floor.map(), &last);
+}
+
+#[test]
+fn test25_cycle() {
+    let mut floor = sea_floor(&[">>>."]);
+    assert_eq!(floor.run_until_stable(100), Outcome::Cycle { start: 0, period: 4 });
+    assert_eq!(floor.steps(), 4);
+    let states: Vec<String> = floor.states().take(5).map(|map| map.to_string()).collect();
+    assert_eq!(states[0], states[4]);
+
+    let mut floor = sea_floor(&["v>.", ">..", "..."]);
+    floor.step();
+    let initial = floor.clone();
+    let Outcome::Cycle { start, period } = floor.run_until_stable(1000) else {
+        panic!("no cycle found");
+    };
+    assert_eq!(floor.steps(), start + period);
+    let maps: Vec<Map> = initial.states().take(start + period + 1).collect();
+    assert_eq!(maps[start - 1], maps[start + period - 1]);
+    assert!(start == 1 || maps[start - 2] != maps[start + period - 2]);
+    assert_eq!(floor.run_until_stable(start + period), Outcome::Limit);
 }
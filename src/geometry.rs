@@ -0,0 +1,302 @@
+/* Axis-aligned boxes of integer points in N dimensions, and sets of disjoint
+   boxes closed under the usual boolean operations. Bounds are inclusive, as
+   in the puzzles: the interval 1..3 contains 3 points. */
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct Interval {
+    pub l: i64,
+    pub u: i64
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.l, self.u)
+    }
+}
+
+impl Interval {
+    pub fn new(l: i64, u: i64) -> Self {
+        assert!(l <= u, "empty interval {}..{}", l, u);
+        Interval { l, u }
+    }
+
+    pub fn singleton(x: i64) -> Self {
+        Interval { l: x, u: x }
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        self.l <= x && x <= self.u
+    }
+
+    pub fn inter(&self, other: &Self) -> Option<Self> {
+        use std::cmp::{min,max};
+        let l = max(self.l, other.l);
+        let u = min(self.u, other.u);
+        if l <= u {
+            Some(Interval {l,u})
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.l <= other.u && other.l <= self.u
+    }
+
+    pub fn includes(&self, other: &Self) -> bool {
+        self.l <= other.l && self.u >= other.u
+    }
+
+    pub fn hull(&self, other: &Self) -> Self {
+        use std::cmp::{min,max};
+        Interval { l: min(self.l, other.l), u: max(self.u, other.u) }
+    }
+
+    pub fn cardinal(&self) -> u64 {
+        (self.u + 1 - self.l) as u64
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct Cuboid<const N: usize> {
+    pub ranges: [Interval; N]
+}
+
+impl<const N: usize> std::fmt::Display for Cuboid<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i,range) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", range)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<const N: usize> Cuboid<N> {
+    pub fn new(ranges: [Interval; N]) -> Self {
+        Cuboid { ranges }
+    }
+
+    pub fn point(p: &[i64; N]) -> Self {
+        Cuboid { ranges: p.map(Interval::singleton) }
+    }
+
+    pub fn contains(&self, p: &[i64; N]) -> bool {
+        self.ranges.iter().zip(p).all(|(r,x)| r.contains(*x))
+    }
+
+    pub fn inter(&self, other: &Self) -> Option<Self> {
+        let mut ranges = self.ranges;
+        for (r,o) in ranges.iter_mut().zip(&other.ranges) {
+            *r = r.inter(o)?;
+        }
+        Some(Cuboid { ranges })
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.ranges.iter().zip(&other.ranges).all(|(r,o)| r.intersects(o))
+    }
+
+    pub fn includes(&self, other: &Self) -> bool {
+        self.ranges.iter().zip(&other.ranges).all(|(r,o)| r.includes(o))
+    }
+
+    pub fn hull(&self, other: &Self) -> Self {
+        let mut ranges = self.ranges;
+        for (r,o) in ranges.iter_mut().zip(&other.ranges) {
+            *r = r.hull(o);
+        }
+        Cuboid { ranges }
+    }
+
+    /* Splits self minus other into at most 2N disjoint slabs: along each
+       axis in turn, the parts lying before and after other are cut off and
+       the remaining core is narrowed to other's range. */
+    pub fn diff(&self, other: &Self) -> Vec<Self> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+
+        let mut r = Vec::new();
+        let mut core = *self;
+        for axis in 0..N {
+            let range = core.ranges[axis];
+            let cut = other.ranges[axis];
+            if range.l < cut.l {
+                let mut slab = core;
+                slab.ranges[axis] = Interval { l: range.l, u: cut.l - 1 };
+                r.push(slab);
+            }
+            if range.u > cut.u {
+                let mut slab = core;
+                slab.ranges[axis] = Interval { l: cut.u + 1, u: range.u };
+                r.push(slab);
+            }
+            core.ranges[axis] = range.inter(&cut).unwrap();
+        }
+        r
+    }
+
+    pub fn volume(&self) -> u64 {
+        self.ranges.iter().map(Interval::cardinal).product()
+    }
+}
+
+/* A union of pairwise disjoint cuboids. */
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct CuboidSet<const N: usize> {
+    cuboids: Vec<Cuboid<N>>
+}
+
+impl<const N: usize> From<Cuboid<N>> for CuboidSet<N> {
+    fn from(cuboid: Cuboid<N>) -> Self {
+        CuboidSet { cuboids: vec![cuboid] }
+    }
+}
+
+impl<const N: usize> CuboidSet<N> {
+    pub fn empty() -> Self {
+        CuboidSet { cuboids: Vec::new() }
+    }
+
+    pub fn cuboids(&self) -> &[Cuboid<N>] {
+        &self.cuboids
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cuboids.is_empty()
+    }
+
+    pub fn contains(&self, p: &[i64; N]) -> bool {
+        self.cuboids.iter().any(|c| c.contains(p))
+    }
+
+    pub fn volume(&self) -> u64 {
+        self.cuboids.iter().map(Cuboid::volume).sum()
+    }
+
+    pub fn bounding_box(&self) -> Option<Cuboid<N>> {
+        let (first, rest) = self.cuboids.split_first()?;
+        Some(rest.iter().fold(*first, |acc, c| acc.hull(c)))
+    }
+
+    fn diff_cuboid(&self, other: &Cuboid<N>) -> Vec<Cuboid<N>> {
+        self.cuboids.iter().flat_map(|c| c.diff(other)).collect()
+    }
+
+    pub fn diff(&self, other: &Self) -> Self {
+        let mut r = self.clone();
+        for c in &other.cuboids {
+            r.cuboids = r.diff_cuboid(c);
+        }
+        r
+    }
+
+    pub fn inter(&self, other: &Self) -> Self {
+        let mut cuboids = Vec::new();
+        for c1 in &self.cuboids {
+            for c2 in &other.cuboids {
+                if let Some(c) = c1.inter(c2) {
+                    cuboids.push(c);
+                }
+            }
+        }
+        CuboidSet { cuboids }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut r = other.diff(self);
+        r.cuboids.extend_from_slice(&self.cuboids);
+        r
+    }
+
+    pub fn sym_diff(&self, other: &Self) -> Self {
+        let mut r = self.diff(other);
+        r.cuboids.append(&mut other.diff(self).cuboids);
+        r
+    }
+}
+
+#[cfg(test)]
+fn random_set<const N: usize>(rng: &mut crate::random::Rng, count: usize) -> CuboidSet<N> {
+    let mut next = |bound: u64| rng.below(bound) as i64;
+    let mut set = CuboidSet::empty();
+    for _ in 0..count {
+        let ranges = [(); N].map(|_| {
+            let l = next(20) - 10;
+            Interval::new(l, l + next(8))
+        });
+        set = set.union(&Cuboid::new(ranges).into());
+    }
+    set
+}
+
+#[cfg(test)]
+fn assert_disjoint<const N: usize>(set: &CuboidSet<N>) {
+    for (i,c1) in set.cuboids().iter().enumerate() {
+        for c2 in &set.cuboids()[(i+1)..] {
+            assert!(!c1.intersects(c2), "{} and {} overlap", c1, c2);
+        }
+    }
+}
+
+#[test]
+fn test_geometry_diff() {
+    let a = Cuboid::new([Interval::new(0, 2), Interval::new(0, 2), Interval::new(0, 2)]);
+    let b = Cuboid::point(&[1, 1, 1]);
+    let pieces = a.diff(&b);
+    assert_eq!(pieces.len(), 6);
+    assert_eq!(pieces.iter().map(Cuboid::volume).sum::<u64>(), 26);
+    assert!(pieces.iter().all(|c| !c.contains(&[1, 1, 1])));
+    assert_eq!(a.diff(&a), vec![]);
+}
+
+#[test]
+fn test_geometry_volume_identities() {
+    let mut rng = crate::random::Rng::new(0x2545f4914f6cdd1d);
+    for _ in 0..100 {
+        let a: CuboidSet<3> = random_set(&mut rng, 3);
+        let b: CuboidSet<3> = random_set(&mut rng, 3);
+        let union = a.union(&b);
+        let inter = a.inter(&b);
+        let diff = a.diff(&b);
+        let sym_diff = a.sym_diff(&b);
+        for set in [&a, &b, &union, &inter, &diff, &sym_diff] {
+            assert_disjoint(set);
+        }
+
+        assert_eq!(union.volume() + inter.volume(), a.volume() + b.volume());
+        assert_eq!(diff.volume() + inter.volume(), a.volume());
+        assert_eq!(sym_diff.volume() + inter.volume(), union.volume());
+
+        let bounds = union.bounding_box().unwrap();
+        assert!(union.cuboids().iter().all(|c| bounds.includes(c)));
+        for x in bounds.ranges[0].l..=bounds.ranges[0].u {
+            for y in bounds.ranges[1].l..=bounds.ranges[1].u {
+                let p = [x, y, 0];
+                let (in_a, in_b) = (a.contains(&p), b.contains(&p));
+                assert_eq!(union.contains(&p), in_a || in_b);
+                assert_eq!(inter.contains(&p), in_a && in_b);
+                assert_eq!(diff.contains(&p), in_a && !in_b);
+                assert_eq!(sym_diff.contains(&p), in_a != in_b);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_geometry_dimensions() {
+    let mut rng = crate::random::Rng::new(88172645463325252);
+    for _ in 0..50 {
+        let a: CuboidSet<2> = random_set(&mut rng, 4);
+        let b: CuboidSet<2> = random_set(&mut rng, 4);
+        assert_eq!(a.union(&b).volume() + a.inter(&b).volume(), a.volume() + b.volume());
+        let a: CuboidSet<4> = random_set(&mut rng, 2);
+        let b: CuboidSet<4> = random_set(&mut rng, 2);
+        assert_eq!(a.union(&b).volume() + a.inter(&b).volume(), a.volume() + b.volume());
+    }
+}
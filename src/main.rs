@@ -1,5 +1,9 @@
 pub mod algebra;
 pub mod mdarray;
+pub mod geometry;
+pub mod netpbm;
+#[cfg(test)]
+pub mod random;
 
 pub mod day1;
 pub mod day2;
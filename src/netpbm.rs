@@ -0,0 +1,88 @@
+/* Writers for the plain (ASCII) netpbm formats: PBM for black and white
+   pictures and PGM for grey levels. Any image viewer opens them, and being
+   text they can be compared in tests. Plain format lines are limited to 70
+   characters, so long rows are wrapped. */
+
+use std::io::{self, Write};
+use std::path::Path;
+
+const MAX_LINE: usize = 70;
+
+/* Writes a PBM image, where true is black. */
+pub fn write_pbm<W: Write + ?Sized>(out: &mut W, width: usize, height: usize,
+                                    pixel: impl Fn(usize, usize) -> bool) -> io::Result<()> {
+    writeln!(out, "P1")?;
+    writeln!(out, "{} {}", width, height)?;
+    for i in 0..height {
+        let row: Vec<u8> = (0..width).map(|j| if pixel(i, j) { b'1' } else { b'0' }).collect();
+        for line in row.chunks(MAX_LINE) {
+            out.write_all(line)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/* Writes a PGM image with grey levels from 0 (black) to maxval (white). */
+pub fn write_pgm<W: Write + ?Sized>(out: &mut W, width: usize, height: usize, maxval: u16,
+                                    value: impl Fn(usize, usize) -> u16) -> io::Result<()> {
+    assert!(maxval > 0, "PGM images need a positive maxval");
+    writeln!(out, "P2")?;
+    writeln!(out, "{} {}", width, height)?;
+    writeln!(out, "{}", maxval)?;
+    for i in 0..height {
+        let mut line = String::new();
+        for j in 0..width {
+            let v = value(i, j);
+            assert!(v <= maxval, "Grey level {} above maxval {}", v, maxval);
+            let v = v.to_string();
+            if !line.is_empty() && line.len() + 1 + v.len() > MAX_LINE {
+                writeln!(out, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&v);
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/* Creates the file at path and lets write fill it. */
+pub fn save(path: &Path, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+    write(&mut out)?;
+    out.flush()
+}
+
+#[test]
+fn test_netpbm_pbm() {
+    let mut out = Vec::new();
+    write_pbm(&mut out, 3, 2, |i, j| (i + j) % 2 == 0).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "P1\n3 2\n101\n010\n");
+
+    let mut out = Vec::new();
+    write_pbm(&mut out, 75, 1, |_, j| j >= 70).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert_eq!(text, format!("P1\n75 1\n{}\n11111\n", "0".repeat(70)));
+}
+
+#[test]
+fn test_netpbm_pgm() {
+    let mut out = Vec::new();
+    write_pgm(&mut out, 3, 2, 5, |i, j| (i * 3 + j) as u16).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "P2\n3 2\n5\n0 1 2\n3 4 5\n");
+
+    let mut out = Vec::new();
+    write_pgm(&mut out, 30, 1, 255, |_, _| 255).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.lines().all(|line| line.len() <= MAX_LINE));
+    assert_eq!(text.split_whitespace().skip(4).count(), 30);
+
+    let path = std::env::temp_dir().join(format!("aoc_test_netpbm_{}.pgm", std::process::id()));
+    save(&path, |out| write_pgm(out, 1, 1, 1, |_, _| 1)).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "P2\n1 1\n1\n1\n");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,19 @@
+/* Deterministic pseudo-random numbers for the randomized tests. */
+
+/* xorshift64: the state must never be zero */
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        assert!(seed != 0, "xorshift64 needs a non-zero seed");
+        Rng(seed)
+    }
+
+    /* A number in 0..bound */
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}